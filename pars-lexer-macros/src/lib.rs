@@ -0,0 +1,535 @@
+//! Procedural macro generating a lexer from rules defined inline in
+//! a Rust module:
+//!
+//! ```rust,ignore
+//! #[macro_use]
+//! extern crate pars_lexer_macros;
+//!
+//! mod calc {
+//!     #[derive(Debug)]
+//!     pub enum Token {
+//!         Number(u64),
+//!         Plus,
+//!     }
+//!
+//!     lexer! {
+//!         token Token;
+//!
+//!         r"[0-9]+" => {
+//!             Some(Token::Number(_lexer_match.as_str().parse().unwrap()))
+//!         }
+//!         r"\+" => { Some(Token::Plus) }
+//!         r"\s+" => { None }
+//!     }
+//! }
+//! ```
+//!
+//! The expansion is the same code `CodeGen::generate` produces for a
+//! build script. The `token` declaration sets the return type of the
+//! actions, it defaults to `Token`. Patterns use the syntax of the
+//! `pars_lexer::regex` module and, as usual, the first rule wins if
+//! several rules match the same input.
+//!
+//! Additional lexer modes can be declared with `mode Name1, Name2;`
+//! after the token type. Rules can then be prefixed with a list of
+//! modes such as `<Name1, Name2>` or `<*>` for all the modes, rules
+//! without a list belong to the `Initial` mode.
+//!
+//! An `error => { action }` declaration placed before the rules sets
+//! the action run on invalid input, see `CodeGen::set_error_action`.
+//!
+//! The action blocks are inserted into the expansion as-is so that
+//! compilation errors within them point at the original code.
+//! Errors in the rules themselves, such as an invalid pattern, are
+//! reported at the offending rule:
+//!
+//! ```rust,compile_fail
+//! #[macro_use]
+//! extern crate pars_lexer_macros;
+//!
+//! mod calc {
+//!     pub enum Token {
+//!         Number,
+//!     }
+//!
+//!     lexer! {
+//!         r"[0-9]+" => { Some(Token::Number) }
+//!         // error: invalid pattern: column 1: unclosed character class
+//!         r"[0-" => { None }
+//!     }
+//! }
+//! # fn main() {}
+//! ```
+
+extern crate proc_macro;
+extern crate pars_lexer;
+
+use proc_macro::{TokenStream, TokenTree, Delimiter, Group, Ident, Literal,
+                 Punct, Spacing, Span};
+
+use pars_lexer::codegen::CodeGen;
+use pars_lexer::dfa::{self, Dfa};
+use pars_lexer::nfa::Nfa;
+use pars_lexer::regex;
+
+/// Prefix of the identifiers used as action placeholders in the
+/// generated code. They're replaced by the actual action blocks once
+/// the code has been tokenized.
+const ACTION_PLACEHOLDER: &str = "__pars_lexer_action_";
+
+/// Placeholder for the error action in the generated code
+const ERROR_PLACEHOLDER: &str = "__pars_lexer_error_action";
+
+/// Placeholder for the token type in the generated code
+const TOKEN_TYPE_PLACEHOLDER: &str = "__pars_lexer_token_type";
+
+#[proc_macro]
+pub fn lexer(input: TokenStream) -> TokenStream {
+    match expand(input) {
+        Ok(ts) => ts,
+        Err((span, message)) => compile_error(span, &message),
+    }
+}
+
+/// A `<modes> "pattern" => { action }` rule
+struct Rule {
+    modes: Vec<String>,
+    pattern: Literal,
+    action: Group,
+}
+
+fn expand(input: TokenStream) -> Result<TokenStream, (Span, String)> {
+    let Input { token_type, modes, error_action, rules } = try!(parse(input));
+
+    let mut nfas: Vec<Option<Nfa>> = vec![None; modes.len()];
+
+    for (i, rule) in rules.iter().enumerate() {
+        let span = rule.pattern.span();
+
+        let pattern =
+            match string_literal_value(&rule.pattern.to_string()) {
+                Some(p) => p,
+                None => return Err((span, "expected a string literal".into())),
+            };
+
+        let mut rule_nfa =
+            try!(regex::parse(&pattern).map_err(|e| {
+                (span, format!("invalid pattern: {}", e))
+            }));
+
+        rule_nfa.concat(Nfa::new_accepting(format!("{}{}",
+                                                   ACTION_PLACEHOLDER, i)));
+
+        for ((name, _), nfa) in modes.iter().zip(nfas.iter_mut()) {
+            if !rule.modes.contains(name) {
+                continue;
+            }
+
+            *nfa = match nfa.take() {
+                Some(mut n) => {
+                    n.combine(rule_nfa.clone());
+                    Some(n)
+                }
+                None => Some(rule_nfa.clone()),
+            };
+        }
+    }
+
+    let mut mode_nfas = Vec::with_capacity(modes.len());
+
+    for ((name, span), nfa) in modes.into_iter().zip(nfas) {
+        match nfa {
+            Some(n) => mode_nfas.push((name, n)),
+            None if name == dfa::INITIAL_MODE =>
+                return Err((span, "no rules".into())),
+            None => return Err((span, "mode without rules".into())),
+        }
+    }
+
+    let dfa = Dfa::from_modes(&mode_nfas);
+
+    let mut gen = CodeGen::new();
+
+    gen.set_token_type(TOKEN_TYPE_PLACEHOLDER);
+
+    if error_action.is_some() {
+        gen.set_error_action(ERROR_PLACEHOLDER);
+    }
+
+    let mut code = Vec::new();
+
+    gen.generate(&dfa, &mut code).unwrap();
+
+    let code = String::from_utf8(code).unwrap();
+
+    let generated: TokenStream =
+        match code.parse() {
+            Ok(ts) => ts,
+            Err(_) => return Err((Span::call_site(),
+                                  "internal error: invalid generated code".into())),
+        };
+
+    let actions: Vec<_> = rules.into_iter().map(|r| r.action).collect();
+
+    Ok(substitute(generated, &actions, error_action.as_ref(), &token_type))
+}
+
+/// Modes and the span of their declaration
+type Modes = Vec<(String, Span)>;
+
+/// Parsed macro input
+struct Input {
+    token_type: TokenStream,
+    modes: Modes,
+    error_action: Option<Group>,
+    rules: Vec<Rule>,
+}
+
+/// Parse the macro input: an optional `token Type;` declaration,
+/// the `mode` declarations, the optional error action and the rules.
+fn parse(input: TokenStream) -> Result<Input, (Span, String)> {
+    let mut tokens = input.into_iter().peekable();
+
+    let mut token_type: TokenStream =
+        "Token".parse().unwrap();
+
+    let is_token_decl =
+        match tokens.peek() {
+            Some(TokenTree::Ident(i)) => i.to_string() == "token",
+            _ => false,
+        };
+
+    if is_token_decl {
+        let decl_span = tokens.next().unwrap().span();
+
+        let mut ty = Vec::new();
+
+        loop {
+            match tokens.next() {
+                Some(TokenTree::Punct(ref p)) if p.as_char() == ';' => break,
+                Some(tt) => ty.push(tt),
+                None => return Err((decl_span, "expected `;` after token type".into())),
+            }
+        }
+
+        if ty.is_empty() {
+            return Err((decl_span, "missing token type".into()));
+        }
+
+        token_type = ty.into_iter().collect();
+    }
+
+    let mut modes = vec![(dfa::INITIAL_MODE.to_owned(), Span::call_site())];
+
+    loop {
+        let is_mode_decl =
+            match tokens.peek() {
+                Some(TokenTree::Ident(i)) => i.to_string() == "mode",
+                _ => false,
+            };
+
+        if !is_mode_decl {
+            break;
+        }
+
+        let decl_span = tokens.next().unwrap().span();
+
+        loop {
+            match tokens.next() {
+                Some(TokenTree::Ident(i)) => {
+                    let name = i.to_string();
+
+                    if modes.iter().any(|(m, _)| *m == name) {
+                        return Err((i.span(), "duplicate mode".into()));
+                    }
+
+                    modes.push((name, i.span()));
+                }
+                Some(tt) => return Err((tt.span(), "expected a mode name".into())),
+                None => return Err((decl_span, "expected a mode name".into())),
+            }
+
+            match tokens.next() {
+                Some(TokenTree::Punct(ref p)) if p.as_char() == ';' => break,
+                Some(TokenTree::Punct(ref p)) if p.as_char() == ',' => (),
+                Some(tt) => return Err((tt.span(), "expected `,` or `;`".into())),
+                None => return Err((decl_span, "expected `;` after modes".into())),
+            }
+        }
+    }
+
+    let mut error_action = None;
+
+    let is_error_decl =
+        match tokens.peek() {
+            Some(TokenTree::Ident(i)) => i.to_string() == "error",
+            _ => false,
+        };
+
+    if is_error_decl {
+        let decl_span = tokens.next().unwrap().span();
+
+        error_action = Some(try!(parse_action(&mut tokens, decl_span)));
+    }
+
+    let mut rules = Vec::new();
+
+    while let Some(mut tt) = tokens.next() {
+        let mut rule_modes = vec![dfa::INITIAL_MODE.to_owned()];
+
+        let is_mode_list =
+            match tt {
+                TokenTree::Punct(ref p) => p.as_char() == '<',
+                _ => false,
+            };
+
+        if is_mode_list {
+            rule_modes = try!(parse_mode_list(&mut tokens, tt.span(), &modes));
+
+            tt = match tokens.next() {
+                Some(tt) => tt,
+                None => return Err((Span::call_site(),
+                                    "expected a pattern string".into())),
+            };
+        }
+
+        let pattern =
+            match tt {
+                TokenTree::Literal(l) => l,
+                tt => return Err((tt.span(), "expected a pattern string".into())),
+            };
+
+        let action = try!(parse_action(&mut tokens, pattern.span()));
+
+        // Rules can optionally be separated by commas
+        let is_comma =
+            match tokens.peek() {
+                Some(TokenTree::Punct(p)) => p.as_char() == ',',
+                _ => false,
+            };
+
+        if is_comma {
+            tokens.next();
+        }
+
+        rules.push(Rule {
+            modes: rule_modes,
+            pattern: pattern,
+            action: action,
+        });
+    }
+
+    Ok(Input {
+        token_type: token_type,
+        modes: modes,
+        error_action: error_action,
+        rules: rules,
+    })
+}
+
+/// Parse `=> { action }`. `span` is the span of the preceding
+/// token, used to report errors.
+fn parse_action<I>(tokens: &mut I, span: Span) -> Result<Group, (Span, String)>
+    where I: Iterator<Item=TokenTree> {
+
+    // `=>` is tokenized as two joint punctuation characters
+    let arrow =
+        match (tokens.next(), tokens.next()) {
+            (Some(TokenTree::Punct(ref eq)), Some(TokenTree::Punct(ref gt))) =>
+                eq.as_char() == '=' && eq.spacing() == Spacing::Joint &&
+                gt.as_char() == '>',
+            _ => false,
+        };
+
+    if !arrow {
+        return Err((span, "expected `=>`".into()));
+    }
+
+    match tokens.next() {
+        Some(TokenTree::Group(ref g)) if g.delimiter() == Delimiter::Brace =>
+            Ok(g.clone()),
+        Some(tt) => Err((tt.span(), "expected a `{ ... }` block".into())),
+        None => Err((span, "missing action block".into())),
+    }
+}
+
+/// Parse a `<Mode1, Mode2>` or `<*>` list, the `<` has already been
+/// consumed.
+fn parse_mode_list<I>(tokens: &mut I,
+                      start: Span,
+                      modes: &Modes) -> Result<Vec<String>, (Span, String)>
+    where I: Iterator<Item=TokenTree> {
+
+    let mut list = Vec::new();
+
+    loop {
+        match tokens.next() {
+            Some(TokenTree::Punct(ref p)) if p.as_char() == '*' && list.is_empty() => {
+                list.extend(modes.iter().map(|(m, _)| m.clone()));
+            }
+            Some(TokenTree::Ident(i)) => {
+                let name = i.to_string();
+
+                if !modes.iter().any(|(m, _)| *m == name) {
+                    return Err((i.span(), "unknown mode".into()));
+                }
+
+                list.push(name);
+            }
+            _ => return Err((start, "unclosed mode list".into())),
+        }
+
+        match tokens.next() {
+            Some(TokenTree::Punct(ref p)) if p.as_char() == '>' => return Ok(list),
+            Some(TokenTree::Punct(ref p)) if p.as_char() == ',' => (),
+            _ => return Err((start, "unclosed mode list".into())),
+        }
+    }
+}
+
+/// Replace the action and token type placeholders in `stream`
+fn substitute(stream: TokenStream,
+              actions: &[Group],
+              error_action: Option<&Group>,
+              token_type: &TokenStream) -> TokenStream {
+    let mut out = Vec::new();
+
+    for tt in stream {
+        match tt {
+            TokenTree::Group(g) => {
+                let mut group = Group::new(g.delimiter(),
+                                           substitute(g.stream(),
+                                                      actions,
+                                                      error_action,
+                                                      token_type));
+                group.set_span(g.span());
+
+                out.push(TokenTree::Group(group));
+            }
+            TokenTree::Ident(i) => {
+                let name = i.to_string();
+
+                if name == TOKEN_TYPE_PLACEHOLDER {
+                    out.extend(token_type.clone());
+                } else if name == ERROR_PLACEHOLDER {
+                    out.push(TokenTree::Group(error_action.unwrap().clone()));
+                } else if let Some(index) = name.strip_prefix(ACTION_PLACEHOLDER) {
+                    let index: usize = index.parse().unwrap();
+
+                    out.push(TokenTree::Group(actions[index].clone()));
+                } else {
+                    out.push(TokenTree::Ident(i));
+                }
+            }
+            tt => out.push(tt),
+        }
+    }
+
+    out.into_iter().collect()
+}
+
+/// Build a `compile_error!("message")` invocation located at `span`
+fn compile_error(span: Span, message: &str) -> TokenStream {
+    let mut bang = Punct::new('!', Spacing::Alone);
+    bang.set_span(span);
+
+    let mut msg = Literal::string(message);
+    msg.set_span(span);
+
+    let mut args = Group::new(Delimiter::Parenthesis,
+                              TokenTree::Literal(msg).into());
+    args.set_span(span);
+
+    let mut semi = Punct::new(';', Spacing::Alone);
+    semi.set_span(span);
+
+    vec![
+        TokenTree::Ident(Ident::new("compile_error", span)),
+        TokenTree::Punct(bang),
+        TokenTree::Group(args),
+        TokenTree::Punct(semi),
+    ].into_iter().collect()
+}
+
+/// Returns the value of a string literal given its source code,
+/// `None` if `lit` isn't a string literal.
+fn string_literal_value(lit: &str) -> Option<String> {
+    if let Some(raw) = lit.strip_prefix('r') {
+        // Raw string: r#"..."#, no escapes
+        let hashes = raw.chars().take_while(|&c| c == '#').count();
+
+        let start = hashes + 1;
+        let end = raw.len() - hashes - 1;
+
+        if raw.len() < start + hashes + 1 || &raw[start - 1..start] != "\"" {
+            return None;
+        }
+
+        return Some(raw[start..end].to_owned());
+    }
+
+    if lit.len() < 2 || !lit.starts_with('"') || !lit.ends_with('"') {
+        return None;
+    }
+
+    let mut value = String::new();
+    let mut chars = lit[1..lit.len() - 1].chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => value.push('\n'),
+            Some('r') => value.push('\r'),
+            Some('t') => value.push('\t'),
+            Some('0') => value.push('\0'),
+            Some('\\') => value.push('\\'),
+            Some('\'') => value.push('\''),
+            Some('"') => value.push('"'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(b) if b < 0x80 => value.push(b as char),
+                    _ => return None,
+                }
+            }
+            Some('u') => {
+                // \u{XXXX}
+                chars.next();
+
+                let hex: String = chars.by_ref().take_while(|&c| c != '}').collect();
+
+                match u32::from_str_radix(&hex, 16).ok()
+                    .and_then(::std::char::from_u32) {
+                    Some(c) => value.push(c),
+                    None => return None,
+                }
+            }
+            Some('\n') => {
+                // Line continuation: skip the leading whitespace of
+                // the next line
+                while chars.peek().map_or(false, |c| c.is_whitespace()) {
+                    chars.next();
+                }
+            }
+            _ => return None,
+        }
+    }
+
+    Some(value)
+}
+
+#[test]
+fn string_literals() {
+    assert_eq!(string_literal_value(r#""[a-z]+""#), Some("[a-z]+".into()));
+    assert_eq!(string_literal_value(r#""\\d+\n""#), Some("\\d+\n".into()));
+    assert_eq!(string_literal_value(r#""\x41\u{430}\"""#), Some("Aа\"".into()));
+    assert_eq!(string_literal_value(r#"r"\d+""#), Some("\\d+".into()));
+    assert_eq!(string_literal_value(r###"r#""\d+""#"###), Some("\"\\d+\"".into()));
+    assert_eq!(string_literal_value("'a'"), None);
+    assert_eq!(string_literal_value("42"), None);
+}
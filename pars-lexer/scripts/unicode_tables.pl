@@ -0,0 +1,165 @@
+#!/usr/bin/env perl
+#
+# Generates `src/unicode_tables.rs` from the Unicode Character
+# Database bundled with Perl's `Unicode::UCD` module:
+#
+#     perl scripts/unicode_tables.pl > src/unicode_tables.rs
+#
+# The generated file is checked in so that building pars-lexer never
+# requires network access or a local copy of the UCD.
+
+use strict;
+use warnings;
+
+use Unicode::UCD qw(prop_invlist prop_values prop_value_aliases all_casefolds);
+
+# Convert an inversion list into a list of `[first, last]` ranges
+sub ranges {
+    my @invlist = @_;
+    my @ranges;
+
+    while (@invlist) {
+        my $first = shift @invlist;
+        my $end = @invlist ? shift @invlist : 0x110000;
+
+        push @ranges, [$first, $end - 1];
+    }
+
+    return @ranges;
+}
+
+# Emit a `const` table of ranges, wrapped at 80 columns
+sub table {
+    my ($name, @ranges) = @_;
+
+    my $out = "pub const $name: &'static [(u32, u32)] = &[\n";
+    my $line = "   ";
+
+    for my $r (@ranges) {
+        my $item = sprintf(" (0x%x, 0x%x),", @$r);
+
+        if (length($line) + length($item) > 79) {
+            $out .= "$line\n";
+            $line = "   ";
+        }
+
+        $line .= $item;
+    }
+
+    $out .= "$line\n" if $line =~ /\S/;
+    $out .= "];\n\n";
+
+    return $out;
+}
+
+# Emit the tables for every value of `$prop` along with an index
+# mapping each value's short and long names to its table
+sub property {
+    my ($prop, $index) = @_;
+
+    my $tables = "";
+    my @entries;
+
+    for my $value (sort(prop_values($prop))) {
+        my ($short, $long) = prop_value_aliases($prop, $value);
+
+        next unless defined $short;
+
+        my $const = uc("${prop}_$short");
+        $const =~ s/[^A-Z0-9]/_/g;
+
+        my @ranges = ranges(prop_invlist("$prop=$short"));
+
+        $tables .= table($const, @ranges);
+
+        push @entries, [$short, $long, $const];
+    }
+
+    my $out = $tables;
+
+    $out .= "pub const $index: &'static [Property] = &[\n";
+
+    for my $e (@entries) {
+        $out .= sprintf("    (\"%s\", \"%s\", %s),\n", @$e);
+    }
+
+    $out .= "];\n\n";
+
+    return $out;
+}
+
+# Emit the simple case folding equivalence classes: every character
+# with a simple case folding (status `C` or `S` in CaseFolding.txt) is
+# listed along with all the other characters folding to the same
+# value.
+sub case_folding {
+    my $folds = all_casefolds();
+    my %classes;
+
+    for my $code (keys %$folds) {
+        my $simple = $folds->{$code}{simple};
+
+        next if !defined($simple) || $simple eq "";
+
+        my $target = hex($simple);
+
+        $classes{$target}{$target} = 1;
+        $classes{$target}{$code} = 1;
+    }
+
+    my %equivalents;
+
+    for my $class (values %classes) {
+        my @members = sort { $a <=> $b } keys %$class;
+
+        for my $c (@members) {
+            $equivalents{$c} = [grep { $_ != $c } @members];
+        }
+    }
+
+    my $out = "/// Simple case folding equivalence classes, sorted by code point\n";
+
+    $out .= "pub const CASE_FOLDING: &'static [(u32, &'static [u32])] = &[\n";
+
+    for my $c (sort { $a <=> $b } keys %equivalents) {
+        my $others = join(", ", map { sprintf("0x%x", $_) } @{$equivalents{$c}});
+
+        $out .= sprintf("    (0x%x, &[%s]),\n", $c, $others);
+    }
+
+    $out .= "];\n";
+
+    return $out;
+}
+
+my $version = Unicode::UCD::UnicodeVersion();
+
+print <<"HEADER";
+// Unicode $version property tables.
+//
+// DO NOT EDIT: this file is generated by `scripts/unicode_tables.pl`.
+
+#![allow(dead_code)]
+
+pub const UNICODE_VERSION: &'static str = "$version";
+
+/// `(short name, long name, ranges)`
+pub type Property = (&'static str, &'static str, &'static [(u32, u32)]);
+
+HEADER
+
+print property("gc", "GENERAL_CATEGORY");
+print property("sc", "SCRIPT");
+
+print table("XID_START", ranges(prop_invlist("XID_Start")));
+print table("XID_CONTINUE", ranges(prop_invlist("XID_Continue")));
+
+print <<'FOOTER';
+pub const BINARY_PROPERTY: &'static [Property] = &[
+    ("XIDS", "XID_Start", XID_START),
+    ("XIDC", "XID_Continue", XID_CONTINUE),
+];
+
+FOOTER
+
+print case_folding();
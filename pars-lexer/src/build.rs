@@ -0,0 +1,251 @@
+//! Helpers for generating lexers from `build.rs` scripts.
+//!
+//! ```rust,no_run
+//! extern crate pars_lexer;
+//!
+//! use pars_lexer::build::Build;
+//!
+//! fn main() {
+//!     // Generates `$OUT_DIR/calc.rs` from `src/calc.parsl`
+//!     Build::new("src/calc.parsl").run().unwrap();
+//! }
+//! ```
+//!
+//! The generated code can then be included in the crate with:
+//!
+//! ```rust,ignore
+//! include!(concat!(env!("OUT_DIR"), "/calc.rs"));
+//! ```
+
+use std::env;
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use codegen::{CodeGen, InputMode};
+use dfa::Dfa;
+use spec::{self, Spec};
+
+/// Lexer generation settings
+pub struct Build {
+    /// Path to the `.parsl` specification
+    spec: PathBuf,
+    /// Name of the generated file. Defaults to the name of the spec
+    /// file with an `.rs` extension.
+    output: Option<String>,
+    /// Output directory. Defaults to `$OUT_DIR`.
+    out_dir: Option<PathBuf>,
+    /// Overrides the `%token` type of the spec if set
+    token_type: Option<String>,
+    codegen: CodeGen,
+}
+
+impl Build {
+    /// Create a new build for the specification at `spec`
+    pub fn new<P: AsRef<Path>>(spec: P) -> Build {
+        Build {
+            spec: spec.as_ref().to_owned(),
+            output: None,
+            out_dir: None,
+            token_type: None,
+            codegen: CodeGen::new(),
+        }
+    }
+
+    /// Set the name of the generated file within the output
+    /// directory
+    pub fn set_output(&mut self, name: &str) {
+        self.output = Some(name.into())
+    }
+
+    /// Set the output directory, by default the file is generated in
+    /// `$OUT_DIR`
+    pub fn set_out_dir<P: AsRef<Path>>(&mut self, dir: P) {
+        self.out_dir = Some(dir.as_ref().to_owned())
+    }
+
+    /// Override the token type set by the `%token` directive
+    pub fn set_token_type(&mut self, t: &str) {
+        self.token_type = Some(t.into())
+    }
+
+    /// Access the code generator to configure its options
+    pub fn codegen_mut(&mut self) -> &mut CodeGen {
+        &mut self.codegen
+    }
+
+    /// Generate the lexer and return the path to the generated
+    /// file. The file is only rewritten if its contents changed in
+    /// order not to trigger needless recompilations.
+    ///
+    /// This also tells cargo to rerun the build script if the spec
+    /// file is modified.
+    pub fn run(&mut self) -> Result<PathBuf, Error> {
+        println!("cargo:rerun-if-changed={}", self.spec.display());
+
+        let spec = try!(Spec::from_file(&self.spec));
+
+        let token_type =
+            match self.token_type {
+                Some(ref t) => t.clone(),
+                None => spec.token_type().to_owned(),
+            };
+
+        self.codegen.set_token_type(&token_type);
+
+        if let Some(action) = spec.error_action() {
+            self.codegen.set_error_action(action);
+        }
+
+        let dfa =
+            match self.codegen.input_mode() {
+                InputMode::Utf8Bytes => Dfa::from_modes_utf8(spec.modes()),
+                _ => Dfa::from_modes(spec.modes()),
+            };
+
+        let mut code = Vec::new();
+
+        try!(self.codegen.generate(&dfa, &mut code));
+
+        let out_dir =
+            match self.out_dir {
+                Some(ref d) => d.clone(),
+                None => match env::var_os("OUT_DIR") {
+                    Some(d) => PathBuf::from(d),
+                    None => return Err(Error::MissingOutDir),
+                },
+            };
+
+        let output =
+            match self.output {
+                Some(ref o) => o.clone(),
+                None => {
+                    let stem = self.spec.file_stem()
+                        .map(|s| s.to_string_lossy().into_owned())
+                        .unwrap_or("lexer".into());
+
+                    format!("{}.rs", stem)
+                }
+            };
+
+        let path = out_dir.join(output);
+
+        try!(write_if_changed(&path, &code));
+
+        Ok(path)
+    }
+}
+
+/// Write `contents` to `path` unless the file already has the exact
+/// same contents.
+fn write_if_changed(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut current = Vec::new();
+
+    let unchanged =
+        File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut current))
+        .map(|_| current == contents)
+        .unwrap_or(false);
+
+    if unchanged {
+        return Ok(());
+    }
+
+    let mut f = try!(File::create(path));
+
+    f.write_all(contents)
+}
+
+/// Lexer generation error
+#[derive(Debug)]
+pub enum Error {
+    /// The specification failed to load
+    Spec(spec::Error),
+    /// The generated file couldn't be written
+    Io(io::Error),
+    /// No output directory was set and `$OUT_DIR` isn't defined.
+    /// `$OUT_DIR` is only set by cargo when running a build script.
+    MissingOutDir,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Spec(ref e) => write!(f, "{}", e),
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+            Error::MissingOutDir =>
+                write!(f, "OUT_DIR isn't set and no output directory was \
+                           provided"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Spec(ref e) => Some(e),
+            Error::Io(ref e) => Some(e),
+            Error::MissingOutDir => None,
+        }
+    }
+}
+
+impl ::std::convert::From<spec::Error> for Error {
+    fn from(e: spec::Error) -> Error {
+        Error::Spec(e)
+    }
+}
+
+impl ::std::convert::From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+#[test]
+fn output_rewrite() {
+    use std::fs;
+
+    let dir = env::temp_dir().join(format!("pars-lexer-build-{}",
+                                           ::std::process::id()));
+
+    fs::create_dir_all(&dir).unwrap();
+
+    let spec_path = dir.join("test.parsl");
+
+    File::create(&spec_path).unwrap()
+        .write_all(b"%%\n[a-z]+ => { Some(Token) }\n").unwrap();
+
+    let mut build = Build::new(&spec_path);
+    build.set_out_dir(&dir);
+
+    let output = build.run().unwrap();
+
+    assert_eq!(output, dir.join("test.rs"));
+
+    let modified = fs::metadata(&output).unwrap().modified().unwrap();
+
+    // Running the build again with the same spec mustn't touch the
+    // output file
+    ::std::thread::sleep(::std::time::Duration::from_millis(20));
+
+    build.run().unwrap();
+
+    assert_eq!(fs::metadata(&output).unwrap().modified().unwrap(), modified);
+
+    // Changing the spec changes the output
+    File::create(&spec_path).unwrap()
+        .write_all(b"%%\n[a-z]+ => { None }\n").unwrap();
+
+    build.run().unwrap();
+
+    let mut code = String::new();
+
+    File::open(&output).unwrap().read_to_string(&mut code).unwrap();
+
+    assert!(code.contains("None"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -1,5 +1,7 @@
 use std::fmt;
-use std::cmp::{min, max};
+use std::cmp::{min, max, Ordering};
+
+use unicode_tables;
 
 /// A character interval matching any character it containis
 /// inclusively
@@ -123,6 +125,88 @@ impl Interval {
     pub fn last(&self) -> u32 {
         self.last
     }
+
+    /// Returns the sequences of byte intervals matching the UTF-8
+    /// encoding of the characters in this interval. Surrogates and
+    /// values beyond U+10FFFF have no encoding and are ignored.
+    ///
+    /// For instance `[\u{80}-\u{10ffff}]` becomes:
+    ///
+    /// ```text
+    /// [c2-df][80-bf]
+    /// [e0][a0-bf][80-bf]
+    /// [e1-ec][80-bf][80-bf]
+    /// [ed][80-9f][80-bf]
+    /// [ee-ef][80-bf][80-bf]
+    /// [f0][90-bf][80-bf][80-bf]
+    /// [f1-f3][80-bf][80-bf][80-bf]
+    /// [f4][80-8f][80-bf][80-bf]
+    /// ```
+    pub fn utf8_sequences(&self) -> Vec<Vec<Interval>> {
+        let mut sequences = Vec::new();
+
+        let scalars = IntervalSet::from_interval(*self)
+            .intersection(&IntervalSet::any());
+
+        for i in scalars.intervals() {
+            utf8_split(i.first, i.last, &mut sequences);
+        }
+
+        sequences
+    }
+}
+
+/// Split `[first-last]` into ranges that can be described by a single
+/// sequence of byte intervals and push them to `sequences`. The
+/// algorithm is the one used by RE2 and Rust's `utf8-ranges`.
+fn utf8_split(first: u32, last: u32, sequences: &mut Vec<Vec<Interval>>) {
+    // First split on encoding length boundaries
+    for &boundary in &[0x7f, 0x7ff, 0xffff] {
+        if first <= boundary && last > boundary {
+            utf8_split(first, boundary, sequences);
+            utf8_split(boundary + 1, last, sequences);
+            return;
+        }
+    }
+
+    if last <= 0x7f {
+        sequences.push(vec![Interval { first: first, last: last }]);
+        return;
+    }
+
+    // Then make sure that every continuation byte covers its full
+    // range, except for the last one
+    for i in 1..4 {
+        let mask = (1u32 << (6 * i)) - 1;
+
+        if first & !mask != last & !mask {
+            if first & mask != 0 {
+                utf8_split(first, first | mask, sequences);
+                utf8_split((first | mask) + 1, last, sequences);
+                return;
+            }
+
+            if last & mask != mask {
+                utf8_split(first, (last & !mask) - 1, sequences);
+                utf8_split(last & !mask, last, sequences);
+                return;
+            }
+        }
+    }
+
+    let mut first_bytes = [0; 4];
+    let mut last_bytes = [0; 4];
+
+    let first_bytes = ::std::char::from_u32(first).unwrap()
+        .encode_utf8(&mut first_bytes).as_bytes();
+    let last_bytes = ::std::char::from_u32(last).unwrap()
+        .encode_utf8(&mut last_bytes).as_bytes();
+
+    let sequence = first_bytes.iter().zip(last_bytes.iter())
+        .map(|(&f, &l)| Interval { first: f as u32, last: l as u32 })
+        .collect();
+
+    sequences.push(sequence);
 }
 
 impl fmt::Debug for Interval {
@@ -139,6 +223,343 @@ impl fmt::Debug for Interval {
     }
 }
 
+/// A set of characters represented as a sorted list of disjoint
+/// intervals. Contiguous intervals are always merged so two sets
+/// containing the same characters compare equal.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct IntervalSet {
+    intervals: Vec<Interval>,
+}
+
+impl IntervalSet {
+    /// Create an empty set
+    pub fn new() -> IntervalSet {
+        IntervalSet {
+            intervals: Vec::new(),
+        }
+    }
+
+    /// Create a set containing all the characters in `i`
+    pub fn from_interval(i: Interval) -> IntervalSet {
+        IntervalSet {
+            intervals: vec![i],
+        }
+    }
+
+    /// Create a set containing all the characters in `intervals`,
+    /// which can be in any order and overlap. Cheaper than calling
+    /// `push` for each of them since the set is only normalized once.
+    pub fn from_intervals<I>(intervals: I) -> IntervalSet
+        where I: IntoIterator<Item=Interval> {
+        let mut set = IntervalSet {
+            intervals: intervals.into_iter().collect(),
+        };
+
+        set.normalize();
+
+        set
+    }
+
+    /// Create a set containing all the Unicode scalar values, that
+    /// is `[\x{0}-\x{10ffff}]` minus the surrogate range
+    /// `[\x{d800}-\x{dfff}]`.
+    pub fn any() -> IntervalSet {
+        IntervalSet {
+            intervals: vec![
+                Interval { first: 0, last: 0xd7ff },
+                Interval { first: 0xe000, last: 0x10ffff },
+            ],
+        }
+    }
+
+    /// Create a set containing all the characters with the Unicode
+    /// general category `name`. Both short and long category names
+    /// are accepted (`Lu` or `Uppercase_Letter`), as well as the
+    /// one-letter category groups (`L` or `Letter`). Names are matched
+    /// loosely: case, spaces, hyphens and underscores are ignored.
+    ///
+    /// Returns `None` if `name` isn't a valid general category.
+    pub fn general_category(name: &str) -> Option<IntervalSet> {
+        IntervalSet::from_property(unicode_tables::GENERAL_CATEGORY, name)
+    }
+
+    /// Create a set containing all the characters belonging to the
+    /// Unicode script `name`, given as either a short (`Cyrl`) or
+    /// long (`Cyrillic`) name.
+    ///
+    /// Returns `None` if `name` isn't a valid script.
+    pub fn script(name: &str) -> Option<IntervalSet> {
+        IntervalSet::from_property(unicode_tables::SCRIPT, name)
+    }
+
+    /// Create a set containing all the characters with the Unicode
+    /// `XID_Start` property, that is the characters that can start an
+    /// identifier.
+    pub fn xid_start() -> IntervalSet {
+        IntervalSet::from_table(unicode_tables::XID_START)
+    }
+
+    /// Create a set containing all the characters with the Unicode
+    /// `XID_Continue` property, that is the characters that can
+    /// appear in an identifier after the first one.
+    pub fn xid_continue() -> IntervalSet {
+        IntervalSet::from_table(unicode_tables::XID_CONTINUE)
+    }
+
+    /// Look up a Unicode property by name. `name` can be:
+    ///
+    /// * a general category: `Lu`, `Letter`, `gc=Nd`...
+    /// * a script: `Greek`, `sc=Cyrl`, `Script=Latin`...
+    /// * a binary property: `XID_Start` or `XID_Continue`.
+    ///
+    /// Returns `None` if the property is unknown.
+    pub fn unicode_property(name: &str) -> Option<IntervalSet> {
+        if let Some(pos) = name.find('=') {
+            let (property, value) = (&name[..pos], &name[pos + 1..]);
+
+            return match &*loose_name(property) {
+                "gc" | "generalcategory" =>
+                    IntervalSet::general_category(value),
+                "sc" | "script" => IntervalSet::script(value),
+                _ => None,
+            };
+        }
+
+        IntervalSet::general_category(name)
+            .or_else(|| IntervalSet::script(name))
+            .or_else(|| IntervalSet::from_property(
+                unicode_tables::BINARY_PROPERTY, name))
+    }
+
+    fn from_property(properties: &[unicode_tables::Property],
+                     name: &str) -> Option<IntervalSet> {
+        let name = loose_name(name);
+
+        properties.iter()
+            .find(|&&(short, long, _)| {
+                loose_name(short) == name || loose_name(long) == name
+            })
+            .map(|&(_, _, table)| IntervalSet::from_table(table))
+    }
+
+    /// Build a set from a generated table. The tables are sorted and
+    /// the ranges are disjoint and non-contiguous.
+    fn from_table(table: &[(u32, u32)]) -> IntervalSet {
+        IntervalSet {
+            intervals: table.iter()
+                .map(|&(first, last)| Interval {
+                    first: first,
+                    last: last,
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns the set closed under Unicode simple case folding: any
+    /// character that folds to the same value as a character in
+    /// `self` is added to the set. For instance `[a-c]` becomes
+    /// `[A-Ca-c]` and `[k]` becomes `[Kk\x{212a}]` (KELVIN SIGN).
+    pub fn case_fold(&self) -> IntervalSet {
+        let table = unicode_tables::CASE_FOLDING;
+
+        let mut folded = self.clone();
+
+        for i in &self.intervals {
+            // Find the first entry of the table within the interval
+            let start =
+                match table.binary_search_by(|&(c, _)| c.cmp(&i.first)) {
+                    Ok(pos) => pos,
+                    Err(pos) => pos,
+                };
+
+            for &(_, equivalents) in table[start..].iter()
+                .take_while(|&&(c, _)| c <= i.last) {
+                for &e in equivalents {
+                    folded.intervals.push(Interval {
+                        first: e,
+                        last: e,
+                    });
+                }
+            }
+        }
+
+        folded.normalize();
+
+        folded
+    }
+
+    /// Add all the characters in `i` to the set. The set is
+    /// normalized after each call, use `from_intervals` to build a
+    /// set from many intervals.
+    pub fn push(&mut self, i: Interval) {
+        self.intervals.push(i);
+        self.normalize();
+    }
+
+    /// Returns the sorted list of disjoint intervals making up the
+    /// set
+    pub fn intervals(&self) -> &[Interval] {
+        &self.intervals
+    }
+
+    /// Returns `true` if the set contains no characters
+    pub fn is_empty(&self) -> bool {
+        self.intervals.is_empty()
+    }
+
+    /// Returns `true` if the set contains `c`
+    pub fn contains(&self, c: u32) -> bool {
+        self.intervals.binary_search_by(|i| {
+            if i.last < c {
+                Ordering::Less
+            } else if i.first > c {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        }).is_ok()
+    }
+
+    /// Returns the set of characters in either `self` or `other`
+    pub fn union(&self, other: &IntervalSet) -> IntervalSet {
+        let mut intervals = self.intervals.clone();
+
+        intervals.extend_from_slice(&other.intervals);
+
+        let mut set = IntervalSet {
+            intervals: intervals,
+        };
+
+        set.normalize();
+
+        set
+    }
+
+    /// Returns the set of characters in both `self` and `other`
+    pub fn intersection(&self, other: &IntervalSet) -> IntervalSet {
+        let mut intervals = Vec::new();
+
+        let mut a = self.intervals.iter().peekable();
+        let mut b = other.intervals.iter().peekable();
+
+        // Both lists are sorted and disjoint so we can walk them in
+        // lockstep, always advancing the interval that ends first.
+        while let (Some(&&ia), Some(&&ib)) = (a.peek(), b.peek()) {
+            let first = max(ia.first, ib.first);
+            let last = min(ia.last, ib.last);
+
+            if first <= last {
+                intervals.push(Interval {
+                    first: first,
+                    last: last,
+                });
+            }
+
+            if ia.last < ib.last {
+                a.next();
+            } else {
+                b.next();
+            }
+        }
+
+        IntervalSet {
+            intervals: intervals,
+        }
+    }
+
+    /// Returns the set of characters in `self` but not in `other`
+    pub fn difference(&self, other: &IntervalSet) -> IntervalSet {
+        self.intersection(&other.negate())
+    }
+
+    /// Returns the set of all Unicode scalar values not in `self`
+    pub fn complement(&self) -> IntervalSet {
+        IntervalSet::any().difference(self)
+    }
+
+    /// Returns the set of all `u32` values not in `self`. Unlike
+    /// `complement` this isn't limited to valid Unicode scalar
+    /// values which lets us handle sets built from
+    /// `Interval::new_any`.
+    fn negate(&self) -> IntervalSet {
+        let mut intervals = Vec::new();
+
+        let mut next_first = Some(0);
+
+        for i in &self.intervals {
+            if let Some(first) = next_first {
+                if i.first > first {
+                    intervals.push(Interval {
+                        first: first,
+                        last: i.first - 1,
+                    });
+                }
+            }
+
+            next_first = i.last.checked_add(1);
+        }
+
+        if let Some(first) = next_first {
+            intervals.push(Interval {
+                first: first,
+                last: u32::max_value(),
+            });
+        }
+
+        IntervalSet {
+            intervals: intervals,
+        }
+    }
+
+    /// Sort the intervals and merge any overlapping or contiguous
+    /// ones
+    fn normalize(&mut self) {
+        self.intervals.sort();
+
+        let mut merged: Vec<Interval> = Vec::with_capacity(self.intervals.len());
+
+        for &i in &self.intervals {
+            if let Some(last) = merged.last_mut() {
+                if i.first <= last.last.saturating_add(1) {
+                    last.last = max(last.last, i.last);
+                    continue;
+                }
+            }
+
+            merged.push(i);
+        }
+
+        self.intervals = merged;
+    }
+}
+
+impl Default for IntervalSet {
+    fn default() -> IntervalSet {
+        IntervalSet::new()
+    }
+}
+
+impl fmt::Debug for IntervalSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{{"));
+
+        for i in &self.intervals {
+            try!(write!(f, "{:?}", i));
+        }
+
+        write!(f, "}}")
+    }
+}
+
+/// Normalize a Unicode property name for loose matching as described
+/// in UAX #44: case, whitespace, hyphens and underscores are ignored.
+fn loose_name(name: &str) -> String {
+    name.chars()
+        .filter(|&c| !c.is_whitespace() && c != '-' && c != '_')
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
 fn write_char(f: &mut fmt::Formatter, c: u32) -> fmt::Result {
     if c <= 0xff {
         let c = c as u8;
@@ -227,3 +648,199 @@ fn intersection() {
     assert_eq!(az.intersect(az), (None, Some(az), None));
     assert_eq!(any.intersect(any), (None, Some(any), None));
 }
+
+#[test]
+fn set_algebra() {
+    let set = |intervals: &[(char, char)]| {
+        let mut s = IntervalSet::new();
+
+        for &(first, last) in intervals {
+            s.push(Interval::new(first, last));
+        }
+
+        s
+    };
+
+    let ae = set(&[('a', 'e')]);
+    let cz = set(&[('c', 'z')]);
+    let az = set(&[('a', 'z')]);
+
+    // Overlapping and contiguous intervals are merged
+    assert_eq!(set(&[('a', 'c'), ('d', 'e')]), ae);
+    assert_eq!(set(&[('c', 'e'), ('a', 'd'), ('e', 'z')]), az);
+    assert_eq!(set(&[('a', 'b'), ('d', 'e')]).intervals().len(), 2);
+
+    // Building the set at once gives the same result
+    let intervals = [('e', 'z'), ('a', 'd'), ('c', 'e'), ('0', '9')];
+
+    assert_eq!(IntervalSet::from_intervals(intervals.iter()
+                                           .map(|&(f, l)| Interval::new(f, l))),
+               set(&intervals));
+    assert_eq!(IntervalSet::from_intervals(vec![]), IntervalSet::default());
+
+    assert_eq!(ae.union(&cz), az);
+    assert_eq!(ae.intersection(&cz), set(&[('c', 'e')]));
+    assert_eq!(az.difference(&set(&[('c', 'e')])),
+               set(&[('a', 'b'), ('f', 'z')]));
+    assert_eq!(ae.difference(&az), IntervalSet::new());
+
+    assert!(az.contains('m' as u32));
+    assert!(!az.contains('A' as u32));
+
+    // [^"\\]
+    let not_quote = set(&[('"', '"'), ('\\', '\\')]).complement();
+
+    assert!(!not_quote.contains('"' as u32));
+    assert!(!not_quote.contains('\\' as u32));
+    assert!(not_quote.contains('a' as u32));
+    assert!(not_quote.contains(0x10ffff));
+    assert!(!not_quote.contains(0xd800));
+
+    assert_eq!(IntervalSet::new().complement(), IntervalSet::any());
+    assert_eq!(IntervalSet::any().complement(), IntervalSet::new());
+    assert_eq!(not_quote.complement(), set(&[('"', '"'), ('\\', '\\')]));
+
+    let any = IntervalSet::from_interval(Interval::new_any());
+
+    assert_eq!(any.difference(&any), IntervalSet::new());
+    assert_eq!(any.intersection(&az), az);
+}
+
+#[test]
+fn unicode_properties() {
+    let lu = IntervalSet::general_category("Lu").unwrap();
+
+    assert_eq!(IntervalSet::general_category("uppercase letter"), Some(lu.clone()));
+    assert!(lu.contains('A' as u32));
+    assert!(lu.contains('Я' as u32));
+    assert!(!lu.contains('a' as u32));
+
+    let l = IntervalSet::general_category("L").unwrap();
+
+    assert_eq!(l.intersection(&lu), lu);
+    assert!(l.contains('ß' as u32));
+
+    let nd = IntervalSet::unicode_property("gc=Nd").unwrap();
+
+    assert!(nd.contains('7' as u32));
+    assert!(nd.contains('٣' as u32));
+    assert!(!nd.contains('x' as u32));
+
+    let cyrillic = IntervalSet::script("Cyrillic").unwrap();
+
+    assert_eq!(IntervalSet::unicode_property("sc=Cyrl"), Some(cyrillic.clone()));
+    assert!(cyrillic.contains('ж' as u32));
+    assert!(cyrillic.contains('Ж' as u32));
+    assert!(!cyrillic.contains('z' as u32));
+
+    let greek = IntervalSet::unicode_property("Greek").unwrap();
+
+    assert!(greek.contains('λ' as u32));
+    assert!(greek.intersection(&cyrillic).is_empty());
+
+    let start = IntervalSet::xid_start();
+    let cont = IntervalSet::unicode_property("XID_Continue").unwrap();
+
+    assert!(start.contains('é' as u32));
+    assert!(!start.contains('_' as u32));
+    assert!(cont.contains('_' as u32));
+    assert!(cont.contains('9' as u32));
+    assert!(start.difference(&cont).is_empty());
+
+    assert_eq!(IntervalSet::unicode_property("NotAProperty"), None);
+    assert_eq!(IntervalSet::unicode_property("sc=Lu"), None);
+}
+
+#[test]
+fn case_folding() {
+    let set = |intervals: &[(char, char)]| {
+        let mut s = IntervalSet::new();
+
+        for &(first, last) in intervals {
+            s.push(Interval::new(first, last));
+        }
+
+        s
+    };
+
+    assert_eq!(set(&[('a', 'z')]).case_fold(), set(&[('A', 'Z'), ('a', 'z'),
+                                                     ('\u{17f}', '\u{17f}'),
+                                                     ('\u{212a}', '\u{212a}')]));
+
+    // Interval spanning the boundary between upper and lower case
+    // letters
+    assert_eq!(set(&[('X', 'c')]).case_fold(), set(&[('A', 'C'), ('X', 'c'),
+                                                     ('x', 'z')]));
+
+    // Characters folding to the same value are equivalent even if
+    // neither of them is the folded form
+    assert_eq!(set(&[('K', 'K')]).case_fold(), set(&[('K', 'K'), ('k', 'k'),
+                                                     ('\u{212a}', '\u{212a}')]));
+    assert_eq!(set(&[('\u{3c2}', '\u{3c2}')]).case_fold(),
+               set(&[('\u{3a3}', '\u{3a3}'), ('\u{3c2}', '\u{3c3}')]));
+
+    let digits = set(&[('0', '9')]);
+
+    assert_eq!(digits.case_fold(), digits);
+
+    let any = IntervalSet::from_interval(Interval::new_any());
+
+    assert_eq!(any.case_fold(), any);
+}
+
+#[test]
+fn utf8_sequences() {
+    fn matches(sequences: &[Vec<Interval>], bytes: &[u8]) -> bool {
+        sequences.iter().any(|seq| {
+            seq.len() == bytes.len() &&
+                seq.iter().zip(bytes).all(|(i, &b)| {
+                    i.first <= b as u32 && b as u32 <= i.last
+                })
+        })
+    }
+
+    let ascii = Interval::new('a', 'z').utf8_sequences();
+
+    assert_eq!(format!("{:?}", ascii), "[[[a-z]]]");
+
+    let cyrillic = Interval::new('\u{400}', '\u{4ff}').utf8_sequences();
+
+    assert_eq!(format!("{:?}", cyrillic), "[[[\\xd0-\\xd3], [\\x80-\\xbf]]]");
+
+    let all = Interval::new('\0', '\u{10ffff}').utf8_sequences();
+
+    assert_eq!(all.len(), 9);
+
+    // The sequences are mutually exclusive and match all the valid
+    // code points
+    let greek_to_cjk = Interval::new('\u{3b1}', '\u{4e00}');
+    let greek_to_cjk_seqs = greek_to_cjk.utf8_sequences();
+
+    let mut buf = [0; 4];
+
+    for cp in 0..0x110000 {
+        let c =
+            match ::std::char::from_u32(cp) {
+                Some(c) => c,
+                None => continue,
+            };
+
+        let bytes = c.encode_utf8(&mut buf).as_bytes();
+
+        let count = all.iter()
+            .filter(|s| matches(::std::slice::from_ref(*s), bytes))
+            .count();
+
+        assert_eq!(count, 1);
+
+        assert_eq!(matches(&greek_to_cjk_seqs, bytes),
+                   greek_to_cjk.first <= cp && cp <= greek_to_cjk.last);
+    }
+
+    // Surrogates are skipped
+    let surrogates = Interval::new('\u{d000}', '\u{e0ff}').utf8_sequences();
+
+    assert!(!matches(&surrogates, &[0xed, 0xa0, 0x80]));
+    assert!(matches(&surrogates, &[0xed, 0x9f, 0xbf]));
+    assert!(matches(&surrogates, &[0xee, 0x80, 0x80]));
+}
@@ -1,13 +1,90 @@
+use std::collections::BTreeMap;
 use std::io::Write;
 use std::io;
 
-use dfa::Dfa;
+use character::{Interval, IntervalSet};
+use dfa::{Dfa, EquivalenceClasses};
+
+/// Unit used to count the columns of the positions reported by the
+/// generated lexer
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColumnUnit {
+    /// UTF-8 bytes
+    Bytes,
+    /// Unicode code points
+    Chars,
+    /// UTF-16 code units, as used by the Language Server Protocol
+    Utf16,
+}
+
+/// Type of input processed by the generated lexer
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InputMode {
+    /// The input is decoded and the DFA runs on Unicode code
+    /// points. The generated lexer reads UTF-8 by default, other
+    /// encodings can be selected with its `Encoding` type.
+    Utf8,
+    /// The DFA runs directly on the input bytes, intervals beyond
+    /// 0xff never match. Use `Match::as_bytes` to access the matched
+    /// input, `Match::as_str` isn't available in this mode. The
+    /// columns are counted in bytes.
+    Bytes,
+    /// The DFA runs on the UTF-8 encoding of the Unicode code points,
+    /// the input is never decoded and invalid UTF-8 simply doesn't
+    /// match. The DFA must be built with `Dfa::from_modes_utf8` (or
+    /// from NFAs converted with `Nfa::to_utf8`), `CodeGen::generate`
+    /// rejects DFAs with moves beyond 0xff in this mode.
+    Utf8Bytes,
+}
+
+/// Where the generated lexer reads its input from
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InputSource {
+    /// The lexer is generic over a `Read` object, owned or borrowed,
+    /// and buffers the input as it's being lexed.
+    Reader,
+    /// `Lexer::new` takes the whole input as a `str` or byte
+    /// slice. No copy is made, `Match::as_str` returns a `&str`
+    /// borrowed from the input so tokens can reference it without
+    /// allocating.
+    Slice,
+}
+
+/// Code used to run the DFA
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Backend {
+    /// Each state is a `match` on the character class of the
+    /// input. Fast but the code grows with the number of states and
+    /// transitions.
+    Match,
+    /// The DFA is encoded in static arrays: a compressed transition
+    /// table indexed by character class and an accepting state
+    /// table, all run by a small driver loop. Smaller than `Match`
+    /// for DFAs with many states, such as long keyword lists.
+    Table,
+}
 
 /// Code generator
 pub struct CodeGen {
     /// Return type of the lexer's `next_token` method. Replaces
     /// `%TOKEN_TYPE%` in the template. Defaults to `Token`.
     token_type: String,
+    /// Unit of the column numbers. Defaults to `ColumnUnit::Chars`.
+    column_unit: ColumnUnit,
+    /// Defaults to `InputMode::Utf8`
+    input_mode: InputMode,
+    /// Defaults to `InputSource::Reader`
+    input_source: InputSource,
+    /// Defaults to `Backend::Match`
+    backend: Backend,
+    /// Maximum length of a match in bytes, if any
+    max_match_length: Option<usize>,
+    /// Maximum number of bytes read past the end of the longest
+    /// match, if any
+    max_lookahead: Option<usize>,
+    /// Action run on invalid input. If `None` a `NoMatch` error is
+    /// returned instead.
+    error_action: Option<String>,
 }
 
 
@@ -15,6 +92,13 @@ impl CodeGen {
     pub fn new() -> CodeGen {
         CodeGen {
             token_type: "Token".into(),
+            column_unit: ColumnUnit::Chars,
+            input_mode: InputMode::Utf8,
+            input_source: InputSource::Reader,
+            backend: Backend::Match,
+            max_match_length: None,
+            max_lookahead: None,
+            error_action: None,
         }
     }
 
@@ -22,15 +106,234 @@ impl CodeGen {
         self.token_type = t.into()
     }
 
+    /// Set the unit used to count columns
+    pub fn set_column_unit(&mut self, unit: ColumnUnit) {
+        self.column_unit = unit
+    }
+
+    /// Set the type of input processed by the lexer
+    pub fn set_input_mode(&mut self, mode: InputMode) {
+        self.input_mode = mode
+    }
+
+    /// Returns the type of input processed by the lexer
+    pub fn input_mode(&self) -> InputMode {
+        self.input_mode
+    }
+
+    /// Set where the lexer reads its input from
+    pub fn set_input_source(&mut self, source: InputSource) {
+        self.input_source = source
+    }
+
+    /// Set the code used to run the DFA
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.backend = backend
+    }
+
+    /// Returns the size in bytes of the code generated for `dfa`
+    /// with the current settings, useful to compare the backends.
+    pub fn code_size(&self, dfa: &Dfa) -> usize {
+        let mut code = Vec::new();
+
+        // Writing to a `Vec` can't fail
+        self.generate(dfa, &mut code).unwrap();
+
+        code.len()
+    }
+
+    /// Limit the length of the matches to `len` bytes. Longer
+    /// matches are reported as `LexerError::LimitExceeded` instead of
+    /// being buffered until the end of the input.
+    pub fn set_max_match_length(&mut self, len: usize) {
+        self.max_match_length = Some(len)
+    }
+
+    /// Limit the number of bytes read past the end of the longest
+    /// match found so far while looking for a longer one. Hitting the
+    /// limit returns `LexerError::LimitExceeded`. Use
+    /// `set_max_match_length` to limit the input read before the
+    /// first match.
+    pub fn set_max_lookahead(&mut self, len: usize) {
+        self.max_lookahead = Some(len)
+    }
+
+    /// Set the code run when the input doesn't match any rule. Like
+    /// the rule actions it's a block of code returning an
+    /// `Option<Token>`, `_lexer_match` covers the invalid input.
+    ///
+    /// In either case the invalid input is skipped up to the next
+    /// character that can start a match, at which point the lexing
+    /// resumes.
+    pub fn set_error_action(&mut self, action: &str) {
+        self.error_action = Some(action.into())
+    }
+
     pub fn generate(&self, dfa: &Dfa, output: &mut Write) -> io::Result<()> {
         let states = dfa.states();
+        let classes = dfa.equivalence_classes();
 
         assert!(!states.is_empty());
 
+        if self.input_mode == InputMode::Utf8Bytes {
+            let unconverted = states.iter()
+                .flat_map(|s| s.move_intervals())
+                .any(|i| i.last() > 0xff);
+
+            if unconverted {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                          "the DFA runs on code points, \
+                                           use Dfa::from_modes_utf8 to \
+                                           build it for InputMode::Utf8Bytes"));
+            }
+        }
+
         let mut code = include_str!("lexer.rs.in").to_owned();
 
         self.template_replace(&mut code, "%TOKEN_TYPE%", &self.token_type);
 
+        let reader = self.input_source == InputSource::Reader;
+
+        self.template_section(&mut code, "READER", reader);
+        self.template_section(&mut code, "SLICE", !reader);
+
+        // Slices are always UTF-8
+        let decode = self.input_mode == InputMode::Utf8 && reader;
+
+        self.template_section(&mut code, "DECODE", decode);
+
+        let buffer = if reader { "&self.buffer" } else { "self.buffer" };
+
+        self.template_replace(&mut code, "%BUFFER%", buffer);
+
+        let (lexer_param, lexer_bound) =
+            if reader {
+                ("R", "R: ::std::io::Read")
+            } else {
+                ("'a", "'a")
+            };
+
+        self.template_replace(&mut code, "%LEXER_PARAM%", lexer_param);
+        self.template_replace(&mut code, "%LEXER_BOUND%", lexer_bound);
+
+        let next_char = if decode { "next_char" } else { "next_utf8_char" };
+
+        let (input_type, input_name, next_input, format_input) =
+            match self.input_mode {
+                InputMode::Utf8 =>
+                    ("char", "character", next_char,
+                     "write!(f, \"{:?}\", input)"),
+                InputMode::Bytes | InputMode::Utf8Bytes =>
+                    ("u8", "byte", "next_byte",
+                     "write!(f, \"b'{}'\", ::std::ascii::escape_default(input))"),
+            };
+
+        self.template_replace(&mut code, "%INPUT_TYPE%", input_type);
+        self.template_replace(&mut code, "%INPUT_NAME%", input_name);
+        self.template_replace(&mut code, "%NEXT_INPUT%", next_input);
+        self.template_replace(&mut code, "%FORMAT_INPUT%", format_input);
+
+        self.template_section(&mut code,
+                              "UTF8",
+                              self.input_mode != InputMode::Bytes);
+
+        let column_unit =
+            match self.input_mode {
+                InputMode::Utf8 | InputMode::Utf8Bytes => self.column_unit,
+                InputMode::Bytes => ColumnUnit::Bytes,
+            };
+
+        // The width of each character is computed from its first
+        // byte `b` in UTF-8, or from the decoded character `c` for
+        // the other encodings. Invalid sequences of `len` bytes are
+        // counted like the U+FFFD replacement character.
+        let (column_unit, column_width, char_column_width, invalid_width) =
+            match column_unit {
+                ColumnUnit::Bytes => ("bytes", "1", "c.len_utf8()", "len"),
+                ColumnUnit::Chars =>
+                    ("characters",
+                     "if b & 0xc0 == 0x80 { 0 } else { 1 }",
+                     "1",
+                     "1"),
+                ColumnUnit::Utf16 =>
+                    ("UTF-16 code units",
+                     "match b { 0x80...0xbf => 0, 0xf0...0xff => 2, _ => 1 }",
+                     "c.len_utf16()",
+                     "1"),
+            };
+
+        self.template_replace(&mut code, "%COLUMN_UNIT%", column_unit);
+        self.template_replace(&mut code, "%COLUMN_WIDTH%", column_width);
+        self.template_replace(&mut code,
+                              "%CHAR_COLUMN_WIDTH%",
+                              char_column_width);
+        self.template_replace(&mut code, "%INVALID_WIDTH%", invalid_width);
+
+        let modes = dfa.modes();
+
+        self.template_replace(&mut code, "%INITIAL_MODE%", &modes[0].0);
+
+        let mut modes_decl = String::new();
+        let mut mode_starts = String::new();
+        let mut mode_can_start = String::new();
+
+        for &(ref name, start) in modes {
+            modes_decl.push_str(&format!("\n    {},", name));
+            mode_starts.push_str(&format!("\nMode::{} => State::State{},",
+                                          name, start));
+
+            mode_can_start.push_str(&format!("\nMode::{} => match input_class(input) {{\n",
+                                             name));
+
+            let start_classes: Vec<usize> = (1..classes.count())
+                .filter(|&class| {
+                    classes.representative(class)
+                        .and_then(|c| states[start].target(c))
+                        .is_some()
+                })
+                .collect();
+
+            if !start_classes.is_empty() {
+                mode_can_start.push_str(&format!("{} => true,\n",
+                                                 class_patterns(&start_classes)));
+            }
+
+            mode_can_start.push_str("_ => false,\n},");
+        }
+
+        self.template_replace(&mut code, "%DECLARE_MODES%", &modes_decl);
+        self.template_replace(&mut code,
+                              "%MATCH_MODE_START_STATE%",
+                              &mode_starts);
+        self.template_replace(&mut code,
+                              "%MATCH_MODE_CAN_START%",
+                              &mode_can_start);
+
+        let no_match =
+            match self.error_action {
+                Some(ref action) =>
+                    format!("let _lexer_match = Match::new(match_start,\n\
+                                                           error_end,\n\
+                                                           {},\n\
+                                                           _error_span{});\n\
+                             \n\
+                             let maybe_token = {};\n\
+                             \n\
+                             Ok(maybe_token)",
+                            buffer,
+                            if decode {
+                                ",\nself.encoding"
+                            } else {
+                                ""
+                            },
+                            action),
+                None => "Err(LexerError::NoMatch(NoMatch {\n\
+                             span: _error_span,\n\
+                             found: _failure.1,\n\
+                             expected: _failure.0.expected(),\n\
+                         }))".into(),
+            };
+
         let mut states_decl = String::new();
 
         for (i, _) in states.iter().enumerate() {
@@ -39,6 +342,10 @@ impl CodeGen {
 
         self.template_replace(&mut code, "%DECLARE_STATES%", &states_decl);
 
+        let expected = self.generate_expected(dfa);
+
+        self.template_replace(&mut code, "%MATCH_EXPECTED%", &expected);
+
         // Declare accepting states
         states_decl.clear();
 
@@ -52,9 +359,32 @@ impl CodeGen {
                               "%DECLARE_ACCEPTING_STATES%",
                               &states_decl);
 
-        let matcher = self.generate_matcher(dfa);
+        let mut tables = self.generate_classes(&classes);
 
-        self.template_replace(&mut code, "%MATCH_INPUT%", &matcher);
+        let next_state =
+            match self.backend {
+                Backend::Match =>
+                    format!("match state {{{}\n}}",
+                            self.generate_matcher(dfa, &classes)),
+                Backend::Table => {
+                    tables.push_str(&self.generate_tables(dfa, &classes));
+
+                    "{\n\
+                      let next = transition(state, class);\n\
+                      \n\
+                      if let Some(s) = next {\n\
+                          if let Some(a) = ACCEPTING[s as usize] {\n\
+                              accepting_state = Some((self.buffer_offset, a));\n\
+                          }\n\
+                      }\n\
+                      \n\
+                      next\n\
+                      }".into()
+                }
+            };
+
+        self.template_replace(&mut code, "%NEXT_STATE%", &next_state);
+        self.template_replace(&mut code, "%TABLES%", &tables);
 
         let accepting_matcher = self.generate_accepting_matcher(dfa);
 
@@ -62,31 +392,43 @@ impl CodeGen {
                               "%MATCH_ACCEPTING_STATE%",
                               &accepting_matcher);
 
+        let limits = self.generate_limits();
+
+        self.template_replace(&mut code, "%CHECK_LIMITS%", &limits);
+
+        self.template_replace(&mut code, "%NO_MATCH%", &no_match);
+
         output.write_all(code.as_bytes())
     }
 
     /// This is where the magic happens: we generate the actual state
-    /// machine used for matching the input. Will replace
-    /// `%MATCH_INPUT%` in the template.
-    fn generate_matcher(&self, dfa: &Dfa) -> String {
+    /// machine used for matching the input. Each state matches on
+    /// the character class of the input. Will be part of
+    /// `%NEXT_STATE%` in the template.
+    fn generate_matcher(&self,
+                        dfa: &Dfa,
+                        classes: &EquivalenceClasses) -> String {
         let mut matcher = String::new();
 
         for (state_idx, state) in dfa.states().iter().enumerate() {
             matcher.push_str(&format!("\nState::State{} => {{\n", state_idx));
 
-            matcher.push_str("match input as u32 {\n");
+            matcher.push_str("match class {\n");
 
-            for (c, &target) in state.move_map() {
+            // Classes leading to each target
+            let mut targets = BTreeMap::new();
 
-                let first = c.first();
-                let last = c.last();
+            for class in 1..classes.count() {
+                let target = classes.representative(class)
+                    .and_then(|c| state.target(c));
 
-                if first == last {
-                    matcher.push_str(&format!("0x{:x} => {{\n", first));
-                } else {
-                    matcher.push_str(&format!("0x{:x}...0x{:x} => {{\n",
-                                              first, last));
+                if let Some(t) = target {
+                    targets.entry(t).or_insert_with(Vec::new).push(class);
                 }
+            }
+
+            for (&target, classes) in &targets {
+                matcher.push_str(&format!("{} => {{\n", class_patterns(classes)));
 
                 if dfa.states()[target].is_accepting() {
                     matcher.push_str(&format!(
@@ -108,6 +450,192 @@ impl CodeGen {
         matcher
     }
 
+    /// Generate the character class lookup used by both backends:
+    /// a dense table for ASCII and a binary search in sorted ranges
+    /// for the rest. Will be part of `%TABLES%` in the template.
+    fn generate_classes(&self, classes: &EquivalenceClasses) -> String {
+        let max_input =
+            match self.input_mode {
+                InputMode::Utf8 => 0x10ffff,
+                InputMode::Bytes | InputMode::Utf8Bytes => 0xff,
+            };
+
+        let mut ascii = vec![0; 0x80];
+        let mut ranges = Vec::new();
+
+        for &(first, last, class) in classes.ranges() {
+            if class == 0 || first > max_input {
+                continue;
+            }
+
+            for c in first..(last.min(0x7f) + 1) {
+                ascii[c as usize] = class;
+            }
+
+            if last >= 0x80 {
+                ranges.push((first.max(0x80), last.min(max_input), class));
+            }
+        }
+
+        let class_type = int_type(classes.count());
+
+        let mut code = String::new();
+
+        code.push_str(&format!("\n/// Character class of each ASCII input\n\
+                                static ASCII_CLASSES: [{}; 0x80] = {};\n",
+                               class_type, int_array(&ascii)));
+
+        code.push_str(&format!("\n/// Character class of the other inputs, as \
+                                sorted (first, last, class) ranges\n\
+                                static CLASS_RANGES: [(u32, u32, {}); {}] = [",
+                               class_type, ranges.len()));
+
+        for &(first, last, class) in &ranges {
+            code.push_str(&format!("(0x{:x}, 0x{:x}, {}), ", first, last, class));
+        }
+
+        code.push_str("];\n");
+
+        code.push_str(&format!("
+/// Returns the character class of `input`
+fn input_class(input: {}) -> usize {{
+    let c = input as u32;
+
+    if c < 0x80 {{
+        return ASCII_CLASSES[c as usize] as usize;
+    }}
+
+    let found = CLASS_RANGES.binary_search_by(|&(first, last, _)| {{
+        if last < c {{
+            ::std::cmp::Ordering::Less
+        }} else if first > c {{
+            ::std::cmp::Ordering::Greater
+        }} else {{
+            ::std::cmp::Ordering::Equal
+        }}
+    }});
+
+    match found {{
+        Ok(i) => CLASS_RANGES[i].2 as usize,
+        Err(_) => 0,
+    }}
+}}
+", self.input_type()));
+
+        code
+    }
+
+    /// Generate the static arrays and the functions used by the table
+    /// backend. Will be part of `%TABLES%` in the template.
+    fn generate_tables(&self,
+                       dfa: &Dfa,
+                       classes: &EquivalenceClasses) -> String {
+        let states = dfa.states();
+
+        // Transition table rows: (class, target) for each state
+        let rows: Vec<Vec<(usize, usize)>> = states.iter().map(|state| {
+            (1..classes.count())
+                .filter_map(|class| {
+                    classes.representative(class)
+                        .and_then(|c| state.target(c))
+                        .map(|target| (class, target))
+                })
+                .collect()
+        }).collect();
+
+        let (base, next, check) = compress_rows(&rows, classes.count());
+
+        let state_type = int_type(states.len());
+
+        let mut tables = String::new();
+
+        tables.push_str(&format!("\n/// Offset of each state's row in `NEXT` \
+                                  and `CHECK`\n\
+                                  static BASE: [u32; {}] = {};\n",
+                                 base.len(), int_array(&base)));
+
+        tables.push_str(&format!("\n/// Target state of each transition\n\
+                                  static NEXT: [{}; {}] = {};\n",
+                                 state_type, next.len(), int_array(&next)));
+
+        tables.push_str(&format!("\n/// State owning each entry of `NEXT`\n\
+                                  static CHECK: [{}; {}] = {};\n",
+                                 state_type, check.len(), int_array(&check)));
+
+        tables.push_str(&format!("\n/// States by index\n\
+                                  static STATES: [State; {}] = [",
+                                 states.len()));
+
+        for i in 0..states.len() {
+            tables.push_str(&format!("State::State{}, ", i));
+        }
+
+        tables.push_str("];\n");
+
+        tables.push_str(&format!("\n/// Accepting state of each state, if any\n\
+                                  static ACCEPTING: [Option<AcceptingState>; {}] = [",
+                                 states.len()));
+
+        for (i, state) in states.iter().enumerate() {
+            if state.is_accepting() {
+                tables.push_str(&format!("Some(AcceptingState::State{}), ", i));
+            } else {
+                tables.push_str("None, ");
+            }
+        }
+
+        tables.push_str("];\n");
+
+        tables.push_str("
+/// Returns the state reached from `state` on an input of character
+/// class `class`, if any
+fn transition(state: State, class: usize) -> Option<State> {
+    let s = state as usize;
+    let slot = BASE[s] as usize + class;
+
+    if CHECK[slot] as usize == s {
+        Some(STATES[NEXT[slot] as usize])
+    } else {
+        None
+    }
+}
+");
+
+        tables
+    }
+
+    /// Generate the list of characters each state has a move on,
+    /// used to report errors. Will replace `%MATCH_EXPECTED%` in the
+    /// template.
+    fn generate_expected(&self, dfa: &Dfa) -> String {
+        let mut expected = String::new();
+
+        for (state_idx, state) in dfa.states().iter().enumerate() {
+            let set = self.input_intervals(state.move_intervals());
+
+            expected.push_str(&format!("\nState::State{} => &[", state_idx));
+
+            for i in set.intervals() {
+                match self.input_mode {
+                    InputMode::Utf8 => {
+                        let first = ::std::char::from_u32(i.first()).unwrap();
+                        let last = ::std::char::from_u32(i.last()).unwrap();
+
+                        expected.push_str(&format!("({:?}, {:?}), ",
+                                                   first, last));
+                    }
+                    InputMode::Bytes | InputMode::Utf8Bytes =>
+                        expected.push_str(&format!("(0x{:02x}, 0x{:02x}), ",
+                                                   i.first(), i.last())),
+                }
+            }
+
+            expected.push_str("],");
+        }
+
+        expected
+    }
+
     /// Generate the code that will run when an accepting state has
     /// been found. This will run the action code associated with the
     /// token.
@@ -133,6 +661,78 @@ impl CodeGen {
         matcher
     }
 
+    /// Generate the checks run after each transition to enforce the
+    /// match length and lookahead limits. Will replace
+    /// `%CHECK_LIMITS%` in the template.
+    fn generate_limits(&self) -> String {
+        let mut checks = String::new();
+
+        if let Some(max) = self.max_match_length {
+            checks.push_str(&format!(
+                "\nif next_state.is_some() && \
+                     self.buffer_offset - match_start > {} {{\n\
+                     return Err(self.limit_exceeded(Limit::MatchLength({0})));\n\
+                 }}\n", max));
+        }
+
+        if let Some(max) = self.max_lookahead {
+            // Until we find an accepting state only the match length
+            // is limited
+            checks.push_str(&format!(
+                "\nif let Some((end, _)) = accepting_state {{\n\
+                     if next_state.is_some() && self.buffer_offset - end > {} {{\n\
+                         return Err(self.limit_exceeded(Limit::Lookahead({0})));\n\
+                     }}\n\
+                 }}\n", max));
+        }
+
+        checks
+    }
+
+    /// Returns the subset of `intervals` that can actually appear in
+    /// the input: Unicode scalar values in UTF-8 mode (so no
+    /// surrogates) or bytes in the other modes.
+    fn input_intervals<'a, I>(&self, intervals: I) -> IntervalSet
+        where I: Iterator<Item=&'a Interval> {
+
+        let set = IntervalSet::from_intervals(intervals.cloned());
+
+        let input =
+            match self.input_mode {
+                InputMode::Utf8 => IntervalSet::any(),
+                InputMode::Bytes | InputMode::Utf8Bytes =>
+                    IntervalSet::from_interval(Interval::new('\0', '\u{ff}')),
+            };
+
+        set.intersection(&input)
+    }
+
+    /// Rust type of the input processed by the lexer
+    fn input_type(&self) -> &'static str {
+        match self.input_mode {
+            InputMode::Utf8 => "char",
+            InputMode::Bytes | InputMode::Utf8Bytes => "u8",
+        }
+    }
+
+    /// Keep or remove the section of `code` between `%BEGIN_<name>%`
+    /// and `%END_<name>%`
+    fn template_section(&self, code: &mut String, name: &str, keep: bool) {
+        let begin = format!("%BEGIN_{}%", name);
+        let end = format!("%END_{}%", name);
+
+        while let Some(b) = code.find(&begin) {
+            let e = code[b..].find(&end).expect("unterminated template section") + b;
+
+            if keep {
+                code.drain(e..(e + end.len()));
+                code.drain(b..(b + begin.len()));
+            } else {
+                code.drain(b..(e + end.len()));
+            }
+        }
+    }
+
     fn template_replace(&self, code: &mut String, template: &str, val: &str) {
         while let Some(m) = code.find(template) {
             code.drain(m..(m + template.len()));
@@ -145,3 +745,295 @@ impl CodeGen {
         }
     }
 }
+
+/// Compress the transition table `rows` using row displacement: the
+/// rows are overlapped in a single `next` array, `base` gives the
+/// offset of each row and `check` the row owning each entry. The
+/// target of `state` on `class` is `next[base[state] + class]` if
+/// `check[base[state] + class] == state`. Unused entries are owned by
+/// the non-existent state `rows.len()`.
+fn compress_rows(rows: &[Vec<(usize, usize)>],
+                 class_count: usize) -> (Vec<usize>, Vec<usize>, Vec<usize>) {
+    let mut base = vec![0; rows.len()];
+    let mut next = Vec::new();
+    let mut check: Vec<usize> = Vec::new();
+
+    // Place the densest rows first, they're the hardest to fit
+    let mut order: Vec<usize> = (0..rows.len()).collect();
+
+    order.sort_by_key(|&s| ::std::cmp::Reverse(rows[s].len()));
+
+    for s in order {
+        let row = &rows[s];
+
+        if row.is_empty() {
+            continue;
+        }
+
+        let fits = |b: usize| {
+            row.iter().all(|&(class, _)| {
+                match check.get(b + class) {
+                    Some(&owner) => owner == rows.len(),
+                    None => true,
+                }
+            })
+        };
+
+        let b = (0..).find(|&b| fits(b)).unwrap();
+
+        for &(class, target) in row {
+            let slot = b + class;
+
+            if slot >= next.len() {
+                next.resize(slot + 1, 0);
+                check.resize(slot + 1, rows.len());
+            }
+
+            next[slot] = target;
+            check[slot] = s;
+        }
+
+        base[s] = b;
+    }
+
+    // Make sure every lookup stays in bounds
+    let len = base.iter().max().cloned().unwrap_or(0) + class_count;
+
+    next.resize(len, 0);
+    check.resize(len, rows.len());
+
+    (base, next, check)
+}
+
+/// Returns the match pattern for the sorted `classes`, consecutive
+/// classes are grouped in ranges
+fn class_patterns(classes: &[usize]) -> String {
+    let mut patterns = Vec::new();
+    let mut i = 0;
+
+    while i < classes.len() {
+        let first = classes[i];
+
+        while i + 1 < classes.len() && classes[i + 1] == classes[i] + 1 {
+            i += 1;
+        }
+
+        let last = classes[i];
+
+        if first == last {
+            patterns.push(first.to_string());
+        } else {
+            patterns.push(format!("{}...{}", first, last));
+        }
+
+        i += 1;
+    }
+
+    patterns.join(" | ")
+}
+
+/// Returns the smallest unsigned type able to hold values up to `max`
+fn int_type(max: usize) -> &'static str {
+    if max <= 0xff {
+        "u8"
+    } else if max <= 0xffff {
+        "u16"
+    } else {
+        "u32"
+    }
+}
+
+/// Format `values` as a Rust array literal
+fn int_array(values: &[usize]) -> String {
+    let values: Vec<_> = values.iter().map(|v| v.to_string()).collect();
+
+    format!("[{}]", values.join(", "))
+}
+
+#[test]
+fn table_backend() {
+    use nfa::Nfa;
+    use regex;
+
+    let mut id = regex::parse("\\p{XID_Start}\\p{XID_Continue}*").unwrap();
+    id.concat(Nfa::new_accepting("Some(Token::Id)".into()));
+
+    let mut number = regex::parse("[0-9]+(\\.[0-9]+)?").unwrap();
+    number.concat(Nfa::new_accepting("Some(Token::Number)".into()));
+
+    id.combine(number);
+
+    let dfa = Dfa::from_nfa(&id);
+
+    let mut gen = CodeGen::new();
+
+    let interval_size = interval_code_size(&gen, &dfa);
+
+    gen.set_backend(Backend::Table);
+
+    let table_size = gen.code_size(&dfa);
+
+    assert!(table_size * 2 < interval_size);
+
+    // Keywords make for many states with few transitions each
+    let keywords = ["as", "break", "const", "continue", "crate", "else",
+                    "enum", "extern", "false", "for", "if", "impl", "in",
+                    "let", "loop", "match", "mod", "move", "mut", "pub",
+                    "ref", "return", "static", "struct", "trait", "true",
+                    "type", "unsafe", "use", "where", "while"];
+
+    let mut nfa = regex::parse(&keywords.join("|")).unwrap();
+    nfa.concat(Nfa::new_accepting("Some(Token::Keyword)".into()));
+
+    let dfa = Dfa::from_nfa(&nfa);
+
+    gen.set_backend(Backend::Match);
+
+    let match_size = gen.code_size(&dfa);
+
+    gen.set_backend(Backend::Table);
+
+    let table_size = gen.code_size(&dfa);
+
+    assert!(table_size < match_size);
+
+    // Every transition survives the compression
+    let rows = vec![
+        vec![(1, 1), (2, 1), (3, 2)],
+        vec![],
+        vec![(0, 0), (3, 1)],
+        vec![(1, 3), (2, 3), (3, 3)],
+    ];
+
+    let (base, next, check) = compress_rows(&rows, 4);
+
+    for (s, row) in rows.iter().enumerate() {
+        for class in 0..4 {
+            let slot = base[s] + class;
+
+            let target =
+                if check[slot] == s {
+                    Some(next[slot])
+                } else {
+                    None
+                };
+
+            let expected = row.iter()
+                .find(|&&(c, _)| c == class)
+                .map(|&(_, t)| t);
+
+            assert_eq!(target, expected);
+        }
+    }
+
+    // The rows overlap
+    assert!(next.len() < 4 * 4);
+}
+
+#[test]
+fn utf8_bytes_input() {
+    use nfa::Nfa;
+    use regex;
+
+    let mut nfa = regex::parse("[a-zé]+|\\p{Greek}").unwrap();
+    nfa.concat(Nfa::new_accepting("Some(Token::Word)".into()));
+
+    let modes = [("Initial".to_owned(), nfa)];
+
+    let mut gen = CodeGen::new();
+
+    gen.set_input_mode(InputMode::Utf8Bytes);
+
+    let mut code = Vec::new();
+
+    // The DFA must be built for bytes
+    let e = gen.generate(&Dfa::from_modes(&modes), &mut code).err().unwrap();
+
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+
+    let dfa = Dfa::from_modes_utf8(&modes);
+
+    assert!(dfa.states().iter()
+            .flat_map(|s| s.move_intervals())
+            .all(|i| i.last() <= 0xff));
+
+    assert!(gen.generate(&dfa, &mut code).is_ok());
+}
+
+/// Size of the code generated for `dfa` by the `Match` backend if it
+/// matched on the input intervals of each state instead of the
+/// character classes, as it did before the classes were introduced
+#[cfg(test)]
+fn interval_code_size(gen: &CodeGen, dfa: &Dfa) -> usize {
+    let classes = dfa.equivalence_classes();
+
+    let mut matcher = String::new();
+
+    for (state_idx, state) in dfa.states().iter().enumerate() {
+        matcher.push_str(&format!("\nState::State{} => {{\n", state_idx));
+
+        matcher.push_str("match input as u32 {\n");
+
+        for (&c, &target) in state.move_map() {
+            let patterns: Vec<_> = gen.input_intervals(Some(&c).into_iter())
+                .intervals().iter()
+                .map(|i| format!("0x{:x}...0x{:x}", i.first(), i.last()))
+                .collect();
+
+            matcher.push_str(&format!("{} => {{\n", patterns.join(" | ")));
+
+            if dfa.states()[target].is_accepting() {
+                matcher.push_str(&format!(
+                    "accepting_state = Some((self.buffer_offset, \
+                                             AcceptingState::State{}));\n",
+                    target));
+            }
+
+            matcher.push_str(&format!("Some(State::State{})\n", target));
+            matcher.push_str("}\n");
+        }
+
+        matcher.push_str("_ => None,\n");
+
+        matcher.push_str("}\n");
+        matcher.push_str("}");
+    }
+
+    // Swap the class lookup and matcher for the interval matcher
+    gen.code_size(dfa)
+        - gen.generate_classes(&classes).len()
+        - gen.generate_matcher(dfa, &classes).len()
+        + matcher.len()
+}
+
+#[test]
+fn class_matcher() {
+    use nfa::Nfa;
+    use regex;
+
+    let mut id = regex::parse("\\p{XID_Start}\\p{XID_Continue}*").unwrap();
+    id.concat(Nfa::new_accepting("Some(Token::Id)".into()));
+
+    let mut number = regex::parse("[0-9]+(\\.[0-9]+)?").unwrap();
+    number.concat(Nfa::new_accepting("Some(Token::Number)".into()));
+
+    id.combine(number);
+
+    let dfa = Dfa::from_nfa(&id);
+
+    let gen = CodeGen::new();
+
+    // The Unicode sets make for over a thousand intervals but only a
+    // handful of classes
+    let intervals: usize = dfa.states().iter()
+        .map(|s| s.move_map().len())
+        .sum();
+
+    assert!(intervals > 1000);
+    assert_eq!(dfa.equivalence_classes().count(), 5);
+
+    let class_size = gen.code_size(&dfa);
+    let interval_size = interval_code_size(&gen, &dfa);
+
+    assert!(class_size * 2 < interval_size);
+}
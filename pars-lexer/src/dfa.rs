@@ -1,14 +1,22 @@
 //! Deterministic Finite Automaton (DFA) implementation.
 
 use nfa::Nfa;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::collections::btree_map::Keys;
+use std::cmp::Ordering;
 use std::fmt;
 
-use character::Interval;
+use character::{Interval, IntervalSet};
+use runtime::Matcher;
+
+/// Name of the mode of DFAs built with `Dfa::from_nfa`
+pub const INITIAL_MODE: &str = "Initial";
 
 pub struct Dfa {
     states: Vec<State>,
+    /// Name and start state of each mode, the first one is the
+    /// initial mode
+    modes: Vec<(String, usize)>,
 }
 
 #[derive(Clone)]
@@ -65,6 +73,65 @@ impl State {
     pub fn is_accepting(&self) -> bool {
         self.accepting.is_some()
     }
+
+    /// Returns the state reached from this one on input `c`, if any
+    pub fn target(&self, c: u32) -> Option<usize> {
+        self.moves.iter()
+            .find(|&(i, _)| i.first() <= c && c <= i.last())
+            .map(|(_, &target)| target)
+    }
+}
+
+/// Partition of the input alphabet in character classes: two
+/// characters are in the same class if every state of the DFA has
+/// the same move on both. Class 0 contains the characters no state
+/// has a move on.
+pub struct EquivalenceClasses {
+    /// Sorted and contiguous `(first, last, class)` ranges covering
+    /// the whole alphabet
+    ranges: Vec<(u32, u32, usize)>,
+    /// Number of classes, including class 0
+    count: usize,
+}
+
+impl EquivalenceClasses {
+    /// Returns the sorted `(first, last, class)` ranges covering the
+    /// whole alphabet. Adjacent ranges always have different classes.
+    pub fn ranges(&self) -> &[(u32, u32, usize)] {
+        &self.ranges
+    }
+
+    /// Returns the number of classes, including class 0 even if
+    /// every character has a move in some state
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Returns the class of character `c`
+    pub fn class(&self, c: u32) -> usize {
+        let found = self.ranges.binary_search_by(|&(first, last, _)| {
+            if last < c {
+                Ordering::Less
+            } else if first > c {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        });
+
+        match found {
+            Ok(i) => self.ranges[i].2,
+            Err(_) => 0,
+        }
+    }
+
+    /// Returns a character belonging to `class`, or `None` if the
+    /// class is empty (which can only happen for class 0)
+    pub fn representative(&self, class: usize) -> Option<u32> {
+        self.ranges.iter()
+            .find(|&&(_, _, c)| c == class)
+            .map(|&(first, _, _)| first)
+    }
 }
 
 impl Dfa {
@@ -73,7 +140,84 @@ impl Dfa {
     /// If the NFA is valid this can't fail, however the DFA can have
     /// up to the square of the number of states of the NFA in the
     /// worst case.
+    ///
+    /// The resulting DFA has a single mode named `INITIAL_MODE`.
     pub fn from_nfa(nfa: &Nfa) -> Dfa {
+        let start =
+            if nfa.state_count() > 0 {
+                Some(0)
+            } else {
+                None
+            };
+
+        let mut dfa = Dfa::build(nfa, &[(INITIAL_MODE.into(), start)]);
+
+        dfa.optimize();
+
+        dfa
+    }
+
+    /// Builds a DFA with several modes (also called start
+    /// conditions), each with its own set of rules. `modes` contains
+    /// the name and the combined NFA of the rules of each mode, the
+    /// first one is the initial mode.
+    ///
+    /// All the modes share the same DFA, only their start state
+    /// differs. A mode without any rule never matches.
+    pub fn from_modes(modes: &[(String, Nfa)]) -> Dfa {
+        let mut dfa = Dfa::unoptimized(modes);
+
+        dfa.optimize();
+
+        dfa
+    }
+
+    /// Builds a DFA like `from_modes` but running on the UTF-8
+    /// encoding of the input: every NFA is converted with
+    /// `Nfa::to_utf8` first. This is the DFA expected by
+    /// `InputMode::Utf8Bytes` lexers.
+    pub fn from_modes_utf8(modes: &[(String, Nfa)]) -> Dfa {
+        let modes: Vec<_> = modes.iter()
+            .map(|(name, nfa)| (name.clone(), nfa.to_utf8()))
+            .collect();
+
+        Dfa::from_modes(&modes)
+    }
+
+    /// Builds the DFA for `modes` like `from_modes` but without
+    /// factoring equivalent states
+    fn unoptimized(modes: &[(String, Nfa)]) -> Dfa {
+        assert!(!modes.is_empty());
+
+        // Lay out all the NFAs one after the other. Since each one
+        // ends with an accepting state with no moves they don't
+        // interfere with one another.
+        let mut nfa = Nfa::new_empty();
+        let mut starts = Vec::with_capacity(modes.len());
+
+        for (name, mode_nfa) in modes {
+            assert!(mode_nfa.state_count() == 0 || mode_nfa.is_accepting());
+
+            // An empty mode has no start state in the NFA, using the
+            // offset would start it at the next mode's rules
+            let start =
+                if mode_nfa.state_count() > 0 {
+                    Some(nfa.state_count())
+                } else {
+                    None
+                };
+
+            starts.push((name.clone(), start));
+
+            nfa.concat(mode_nfa.clone());
+        }
+
+        Dfa::build(&nfa, &starts)
+    }
+
+    /// Run the subset construction on `nfa` from each of the
+    /// `starts` states. Modes without a start state never match.
+    fn build(nfa: &Nfa, starts: &[(String, Option<usize>)]) -> Dfa {
         // We need a temporary state holding the correspondance
         // between each DFA state and the corresponding NFA states
         struct DState {
@@ -112,10 +256,35 @@ impl Dfa {
             }
         }
 
-        // We start from ε-closure of state (0) of the NFA and work
-        // our way through recursively.
-        let epsi_0 = nfa.epsilon_closure(&[0]);
-        let mut dfa_states = vec![DState::from_nfa_states(nfa, epsi_0)];
+        // We start from the ε-closure of the start state of each
+        // mode and work our way through recursively.
+        let mut dfa_states: Vec<DState> = Vec::new();
+        let mut modes = Vec::with_capacity(starts.len());
+
+        for &(ref name, start) in starts {
+            let closure =
+                match start {
+                    Some(start) => {
+                        let mut c = nfa.epsilon_closure(&[start]);
+                        c.sort();
+                        c.dedup();
+                        c
+                    }
+                    // Empty mode
+                    None => Vec::new(),
+                };
+
+            let state =
+                match dfa_states.iter().position(|s| s.nfa_states == closure) {
+                    Some(pos) => pos,
+                    None => {
+                        dfa_states.push(DState::from_nfa_states(nfa, closure));
+                        dfa_states.len() - 1
+                    }
+                };
+
+            modes.push((name.clone(), state));
+        }
 
         let mut cur_state = 0;
 
@@ -153,153 +322,188 @@ impl Dfa {
 
         // The conversion is done, we can drop the NFA states
         // altogether
-        let mut dfa =
-            Dfa {
-                states: dfa_states.into_iter().map(|s| s.dfa_state).collect()
-            };
-
-        // XXX before optimizing the DFA we could attempt to merge
-        // contiguous intervals to potentially detect more redundant
-        // states.
-        dfa.optimize();
-
-        dfa
+        Dfa {
+            states: dfa_states.into_iter().map(|s| s.dfa_state).collect(),
+            modes: modes,
+        }
     }
 
-    /// Optimize the DFA by factoring equivalent states.
+    /// Optimize the DFA by factoring equivalent states, using
+    /// Hopcroft's partition refinement algorithm. The result is the
+    /// minimal DFA, the start state of the initial mode stays state
+    /// 0.
     fn optimize(&mut self) {
+        let classes = self.equivalence_classes();
+        let class_count = classes.count();
+
+        // Missing moves go to an extra "dead" state which loops on
+        // itself. Class 0 has no moves at all so we can ignore it.
+        let dead = self.states.len();
+        let state_count = dead + 1;
+
+        // `predecessors[class][target]` lists the states moving to
+        // `target` on `class`
+        let mut predecessors = vec![vec![Vec::new(); state_count]; class_count];
+
+        for (class, preds) in predecessors.iter_mut().enumerate().skip(1) {
+            let c = classes.representative(class).unwrap();
+
+            for (i, state) in self.states.iter().enumerate() {
+                let target = state.target(c).unwrap_or(dead);
+
+                preds[target].push(i);
+            }
+
+            preds[dead].push(dead);
+        }
+
         // First we partition the states to isolate the accepting
         // states.
-        let mut partition: Vec<Vec<usize>> = Vec::new();
+        let mut blocks: Vec<Vec<usize>> = Vec::new();
+        let mut block_of = vec![0; state_count];
 
-        for (i, s) in self.states.iter().enumerate() {
+        {
+            let mut by_accepting = BTreeMap::new();
 
-            let mut found = false;
+            for (i, b) in block_of.iter_mut().enumerate() {
+                let accepting = self.states.get(i).and_then(|s| s.accepting());
 
-            // See if we already have a partition with the same accepting
-            // state
-            for p in partition.iter_mut() {
+                let block =
+                    *by_accepting.entry(accepting).or_insert_with(|| {
+                        blocks.push(Vec::new());
+                        blocks.len() - 1
+                    });
 
-                if self.states[p[0]].accepting() == s.accepting() {
-                    p.push(i);
-                    found = true;
-                    break;
-                }
+                blocks[block].push(i);
+                *b = block;
             }
+        }
+
+        // Splitters yet to be processed. All the initial blocks but
+        // the largest are enough.
+        let mut pending = Vec::new();
+        let mut is_pending = vec![vec![false; class_count]; blocks.len()];
+
+        let largest = (0..blocks.len()).max_by_key(|&b| blocks[b].len()).unwrap();
 
-            if !found {
-                // New partition
-                partition.push(vec![i]);
+        for (block, classes) in is_pending.iter_mut().enumerate() {
+            if block == largest {
+                continue;
+            }
+
+            for (class, p) in classes.iter_mut().enumerate().skip(1) {
+                pending.push((block, class));
+                *p = true;
             }
         }
 
-        loop {
-            let mut next_partition: Vec<Vec<usize>> = Vec::new();
-            let mut subgroup_start;
-
-            for group in &partition {
-
-                subgroup_start = next_partition.len();
-
-                for &state_idx in group {
-                    let state = &self.states[state_idx];
-
-                    // See if this state fits in any of our current
-                    // subgroups
-                    let mut found_equiv = false;
-
-                    for sub in &mut next_partition[subgroup_start..] {
-                        // Pick the first state in the subgroup as a
-                        // representative
-                        let &s = sub.first().unwrap();
-
-                        let sub_state = &self.states[s];
-
-                        // Check if both states move on the same
-                        // intervals. If it's not the case we can
-                        // directly assume that they belong to
-                        // different partitions.
-                        let mut equivalent =
-                            sub_state.move_intervals()
-                            .eq(state.move_intervals());
-
-                        if equivalent {
-                            // Both states move on the same keys,
-                            // they could be equivalent
-                            for (i, &s) in sub_state.move_map() {
-                                let other = state.move_map()[i];
-
-                                if other == s {
-                                    continue;
-                                }
-
-                                // We have a different transition but
-                                // there's still hope: if we move to a
-                                // state in the same partition then we
-                                // can still consider the states as
-                                // equivalent.
-                                for p in &partition {
-                                    // Check if the target of both
-                                    // moves land in the same
-                                    // partition
-                                    if p.iter().any(|&i| i == s) {
-                                        equivalent =
-                                            p.iter()
-                                            .any(|&i| i == other);
-                                        break;
-                                    }
-                                }
-
-                                if !equivalent {
-                                    break;
-                                }
-                            }
-                        };
+        let mut marked = vec![false; state_count];
 
-                        if equivalent {
-                            // We can push to this subgroup
-                            sub.push(state_idx);
-                            found_equiv = true;
-                            break;
-                        }
-                    }
+        while let Some((splitter, class)) = pending.pop() {
+            is_pending[splitter][class] = false;
+
+            // States moving into the splitter on `class`, by block
+            let mut touched: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+
+            for &target in &blocks[splitter] {
+                for &s in &predecessors[class][target] {
+                    touched.entry(block_of[s]).or_default().push(s);
+                }
+            }
 
-                    if !found_equiv {
-                        // Looks like we need a new subgroup
-                        next_partition.push(vec![state_idx]);
+            for (block, moving) in touched {
+                if moving.len() == blocks[block].len() {
+                    // Nothing to split
+                    continue;
+                }
+
+                for &s in &moving {
+                    marked[s] = true;
+                }
+
+                let rest: Vec<usize> = blocks[block].iter()
+                    .cloned()
+                    .filter(|&s| !marked[s])
+                    .collect();
+
+                for &s in &moving {
+                    marked[s] = false;
+                }
+
+                let new_block = blocks.len();
+
+                for &s in &moving {
+                    block_of[s] = new_block;
+                }
+
+                blocks[block] = rest;
+                blocks.push(moving);
+                is_pending.push(vec![false; class_count]);
+
+                let smaller_new = blocks[new_block].len() < blocks[block].len();
+                let block_pending = is_pending[block].clone();
+
+                for (c, &was_pending) in block_pending.iter().enumerate().skip(1) {
+                    // If `block` is still pending both halves must be
+                    // processed, otherwise the smallest one is enough
+                    let split =
+                        if smaller_new || was_pending {
+                            new_block
+                        } else {
+                            block
+                        };
+
+                    if !is_pending[split][c] {
+                        pending.push((split, c));
+                        is_pending[split][c] = true;
                     }
                 }
             }
+        }
 
-            let done = partition.len() == next_partition.len();
+        // Order the blocks by their first state so that the initial
+        // mode keeps starting at 0. The real states equivalent to the
+        // dead state (empty modes) are kept but the moves to them are
+        // dropped.
+        let mut order: Vec<usize> = (0..blocks.len())
+            .filter(|&b| blocks[b].iter().any(|&s| s != dead))
+            .collect();
 
-            partition = next_partition;
+        order.sort_by_key(|&b| blocks[b].iter().min());
 
-            if done {
-                break;
+        let mut index = vec![None; blocks.len()];
+
+        for (pos, &b) in order.iter().enumerate() {
+            if !blocks[b].contains(&dead) {
+                index[b] = Some(pos);
             }
         }
 
-        // At this point all the states within a partition should be
-        // equivalent and we can factor them by retaining a single
-        // group per state.
-        let optimized: Vec<_> = partition.iter().map(|p| {
-            // Keep only the first state in the partition
-            let mut s = self.states[p[0]].clone();
-
-            // Rewrite the move map to point to the correct partition
-            for s in s.move_map_mut().values_mut() {
-                for (pos, p) in partition.iter().enumerate() {
-                    if p.iter().any(|&i| i == *s) {
-                        *s = pos;
-                        break;
-                    }
+        let optimized: Vec<_> = order.iter().map(|&b| {
+            // All the states in the block are equivalent, keep the
+            // first one
+            let first = *blocks[b].iter().min().unwrap();
+            let state = &self.states[first];
+
+            let mut moves = BTreeMap::new();
+
+            for (&i, &target) in state.move_map() {
+                if let Some(target) = index[block_of[target]] {
+                    moves.insert(i, target);
                 }
             }
 
-            s
+            State {
+                moves: moves,
+                accepting: state.accepting.clone(),
+            }
         }).collect();
 
+        for &mut (_, ref mut start) in self.modes.iter_mut() {
+            *start = order.iter()
+                .position(|&b| b == block_of[*start])
+                .unwrap();
+        }
 
         self.states = optimized;
     }
@@ -311,86 +515,179 @@ impl Dfa {
     /// move on `[a-z]` we wouldn't know which one to use when
     /// matching a character 'a' in the input stream.
     fn resolve_intersections(move_set: &mut BTreeMap<Interval, Vec<usize>>) {
+        // Intervals that intersect must be "split" into a set of
+        // mutually-exclusive character sets.
+        //
+        // For instance if we have:
+        //   [0-5] => (1, 2, 3)
+        //   [2-8] => (2, 4)
+        //
+        // We must handle the intersection on input [2-5]
+        // by creating:
+        //
+        //   [0-1] => (1, 2, 3)    # Part exclusize to [0-5]
+        //   [2-5] => (1, 2, 3, 4) # Intersection
+        //   [6-8] => (2, 4)       # Part exclusive to [2-8]
+        let mut groups: Vec<(IntervalSet, Vec<usize>)> = Vec::new();
+
+        for (&interval, states) in move_set.iter() {
+            // The part of `interval` that doesn't intersect any
+            // group yet
+            let mut rest = IntervalSet::from_interval(interval);
+
+            let mut next_groups = Vec::with_capacity(groups.len() + 1);
+
+            for (set, set_states) in groups {
+                let inter = set.intersection(&rest);
+
+                if inter.is_empty() {
+                    next_groups.push((set, set_states));
+                    continue;
+                }
 
-        while let Some((a, b)) = Dfa::find_intersection(move_set) {
-            // Intervals intersect, we need to "split" them into a
-            // subset of mutually-exclusive intervals.
-            //
-            // For instance if we have:
-            //   [0-5] => (1, 2, 3)
-            //   [2-8] => (2, 4)
-            //
-            // We must handle the intersection on input [2-5]
-            // by creating:
-            //
-            //   [0-1] => (1, 2, 3)    # Part exclusize to [0-5]
-            //   [2-5] => (1, 2, 3, 4) # Intersection
-            //   [6-8] => (2, 4)       # Part exclusive to [2-8]
-            let a_states = move_set.remove(&a).unwrap();
-            let b_states = move_set.remove(&b).unwrap();
-
-            let mut ab_states: Vec<_> =
-                a_states.iter()
-                .chain(b_states.iter())
-                .map(|&u| u).collect();
-
-            ab_states.sort();
-            ab_states.dedup();
-
-            let (left, inter, right) = a.intersect(b);
-
-            // a and b are known to intersect, inter can't be `None`.
-            let inter = inter.unwrap();
-
-            move_set.insert(inter, ab_states);
-
-            if let Some(right) = right {
-                // The right part can belong either to `a` or `b`
-                // depending on which one extends further.
-                let states =
-                    if a.last() > b.last() {
-                        a_states.clone()
-                    } else {
-                        b_states
-                    };
+                let outside = set.difference(&rest);
+
+                if !outside.is_empty() {
+                    next_groups.push((outside, set_states.clone()));
+                }
+
+                rest = rest.difference(&set);
 
-                move_set.insert(right, states);
+                let mut inter_states = set_states;
+
+                inter_states.extend_from_slice(states);
+                inter_states.sort();
+                inter_states.dedup();
+
+                next_groups.push((inter, inter_states));
             }
 
-            if let Some(left) = left {
-                move_set.insert(left, a_states);
+            if !rest.is_empty() {
+                next_groups.push((rest, states.clone()));
             }
+
+            groups = next_groups;
         }
-    }
 
-    /// Return the first pair of intervals that intersect or `None` if
-    /// all intervals are inclusive
-    fn find_intersection(move_set: &BTreeMap<Interval, Vec<usize>>)
-                         -> Option<(Interval, Interval)> {
-        let mut iter = move_set.keys();
+        // Merge the groups leading to the same states, this way
+        // contiguous intervals with the same target end up as a
+        // single move.
+        let mut by_states: BTreeMap<Vec<usize>, IntervalSet> = BTreeMap::new();
 
-        // Since the intervals are sorted in the map we know that
-        // intersecting intervals have to be contiguous when
-        // iterating. So we can just check them two at a time.
-        if let Some(&first) = iter.next() {
-            let mut a = first;
+        for (set, states) in groups {
+            let merged =
+                match by_states.get(&states) {
+                    Some(s) => s.union(&set),
+                    None => set,
+                };
 
-            while let Some(&b) = iter.next() {
-                if a.intersects(b) {
-                    return Some((a, b));
-                }
+            by_states.insert(states, merged);
+        }
+
+        move_set.clear();
 
-                a = b
+        for (states, set) in by_states {
+            for &i in set.intervals() {
+                move_set.insert(i, states.clone());
             }
         }
-
-        None
     }
 
     /// Returns the vector of states of this DFA
     pub fn states(&self) -> &Vec<State> {
         &self.states
     }
+
+    /// Returns the name and start state of each mode. The first mode
+    /// is the initial one.
+    pub fn modes(&self) -> &[(String, usize)] {
+        &self.modes
+    }
+
+    /// Returns a matcher running this DFA directly on some input,
+    /// without generating any code
+    pub fn matcher(&self) -> Matcher<'_> {
+        Matcher::new(self)
+    }
+
+    /// Computes the partition of the input alphabet in equivalence
+    /// classes. Matching can then be done on class IDs instead of
+    /// characters, which makes for much smaller transition tables
+    /// when the rules use large Unicode sets.
+    pub fn equivalence_classes(&self) -> EquivalenceClasses {
+        // Split the alphabet at every interval boundary, within each
+        // segment all the states have the same moves.
+        let mut bounds = BTreeSet::new();
+
+        bounds.insert(0);
+
+        for state in &self.states {
+            for i in state.move_intervals() {
+                bounds.insert(i.first());
+
+                if i.last() < 0xffff_ffff {
+                    bounds.insert(i.last() + 1);
+                }
+            }
+        }
+
+        let bounds: Vec<u32> = bounds.into_iter().collect();
+
+        // Target of every state for each segment
+        let mut signatures = vec![Vec::with_capacity(self.states.len());
+                                  bounds.len()];
+
+        for state in &self.states {
+            let mut moves = state.move_map().iter().peekable();
+
+            for (signature, &b) in signatures.iter_mut().zip(bounds.iter()) {
+                while moves.peek().map(|&(i, _)| i.last() < b) == Some(true) {
+                    moves.next();
+                }
+
+                let target =
+                    match moves.peek() {
+                        Some(&(i, &t)) if i.first() <= b => Some(t),
+                        _ => None,
+                    };
+
+                signature.push(target);
+            }
+        }
+
+        let mut classes = BTreeMap::new();
+
+        classes.insert(vec![None; self.states.len()], 0);
+
+        let mut ranges: Vec<(u32, u32, usize)> = Vec::new();
+
+        for (k, signature) in signatures.into_iter().enumerate() {
+            let next_class = classes.len();
+            let class = *classes.entry(signature).or_insert(next_class);
+
+            let first = bounds[k];
+            let last =
+                match bounds.get(k + 1) {
+                    Some(&b) => b - 1,
+                    None => 0xffff_ffff,
+                };
+
+            match ranges.last_mut() {
+                Some(r) if r.2 == class => {
+                    r.1 = last;
+                    continue;
+                }
+                _ => (),
+            }
+
+            ranges.push((first, last, class));
+        }
+
+        EquivalenceClasses {
+            ranges: ranges,
+            count: classes.len(),
+        }
+    }
 }
 
 impl fmt::Debug for Dfa {
@@ -407,3 +704,193 @@ impl fmt::Debug for Dfa {
         Ok(())
     }
 }
+
+#[test]
+fn equivalence_classes() {
+    use regex;
+
+    // [a-z]+ | [0-9a-f]+h | \p{Greek}
+    let mut nfa = regex::parse("[a-z]+").unwrap();
+    nfa.concat(Nfa::new_accepting("Id".into()));
+
+    let mut hex = regex::parse("[0-9a-f]+h").unwrap();
+    hex.concat(Nfa::new_accepting("Hex".into()));
+    nfa.combine(hex);
+
+    let mut greek = regex::parse("\\p{Greek}").unwrap();
+    greek.concat(Nfa::new_accepting("Greek".into()));
+    nfa.combine(greek);
+
+    let dfa = Dfa::from_nfa(&nfa);
+    let classes = dfa.equivalence_classes();
+
+    // 0-9, a-f, h, g|i-z, Greek and the rest
+    assert_eq!(classes.count(), 6);
+
+    assert_eq!(classes.class('!' as u32), 0);
+    assert_eq!(classes.class(0x10ffff), 0);
+    assert_eq!(classes.class(0xffff_ffff), 0);
+    assert_eq!(classes.class('a' as u32), classes.class('f' as u32));
+    assert_eq!(classes.class('g' as u32), classes.class('z' as u32));
+    assert!(classes.class('a' as u32) != classes.class('g' as u32));
+    assert!(classes.class('h' as u32) != classes.class('g' as u32));
+    assert_eq!(classes.class('α' as u32), classes.class('ω' as u32));
+
+    // The ranges cover the whole alphabet without gaps and adjacent
+    // ranges are merged
+    let ranges = classes.ranges();
+
+    assert_eq!(ranges[0].0, 0);
+    assert_eq!(ranges[ranges.len() - 1].1, 0xffff_ffff);
+
+    for w in ranges.windows(2) {
+        assert_eq!(w[0].1 + 1, w[1].0);
+        assert!(w[0].2 != w[1].2);
+    }
+
+    // Characters in the same class have the same moves in every state
+    for c in 0..0x800 {
+        let class = classes.class(c);
+        let r = classes.representative(class).unwrap();
+
+        for state in dfa.states() {
+            assert_eq!(state.target(c), state.target(r));
+        }
+    }
+}
+
+#[test]
+fn empty_modes() {
+    use regex;
+
+    let mut x = regex::parse("x").unwrap();
+    x.concat(Nfa::new_accepting("X".into()));
+
+    let mut word = regex::parse("[a-z]+").unwrap();
+    word.concat(Nfa::new_accepting("Word".into()));
+
+    // An empty mode between two non-empty ones
+    let modes = [
+        ("Initial".to_owned(), x),
+        ("Empty".to_owned(), Nfa::new_empty()),
+        ("Other".to_owned(), word),
+    ];
+
+    for dfa in &[Dfa::from_modes(&modes), Dfa::unoptimized(&modes)] {
+        let empty = dfa.modes()[1].1;
+
+        assert!(empty != dfa.modes()[2].1);
+        assert!(!dfa.states()[empty].has_moves());
+        assert!(!dfa.states()[empty].is_accepting());
+
+        let mut matcher = dfa.matcher();
+
+        assert_eq!(matcher.longest_match("xx"), Some(("X", 1)));
+
+        matcher.set_mode("Empty");
+        assert_eq!(matcher.longest_match("hello"), None);
+        assert_eq!(matcher.longest_match("x"), None);
+
+        matcher.set_mode("Other");
+        assert_eq!(matcher.longest_match("hello"), Some(("Word", 5)));
+    }
+
+    // An empty initial mode
+    let dfa = Dfa::from_modes(&[("Initial".to_owned(), Nfa::new_empty()),
+                                modes[2].clone()]);
+
+    assert_eq!(dfa.matcher().longest_match("hello"), None);
+}
+
+#[test]
+fn minimization() {
+    use regex;
+
+    // Build a mode from `rules`, the first one takes precedence
+    fn mode(name: &str, rules: &[&str]) -> (String, Nfa) {
+        let mut nfa = Nfa::new_empty();
+
+        for (i, pattern) in rules.iter().enumerate() {
+            let mut rule = regex::parse(pattern).unwrap();
+            rule.concat(Nfa::new_accepting(format!("rule {}", i)));
+
+            if i == 0 {
+                nfa = rule;
+            } else {
+                nfa.combine(rule);
+            }
+        }
+
+        (name.to_owned(), nfa)
+    }
+
+    // Build the DFA for `modes` with and without optimization
+    fn build(modes: &[(String, Nfa)]) -> (Dfa, Dfa) {
+        let mut dfa = Dfa::from_modes(modes);
+
+        // Optimizing a minimal DFA doesn't change anything
+        let states = dfa.states().len();
+        dfa.optimize();
+        assert_eq!(dfa.states().len(), states);
+
+        (Dfa::unoptimized(modes), dfa)
+    }
+
+    // The known minimal sizes
+    let (_, dfa) = build(&[mode("Initial", &["(a|b)*abb"])]);
+    assert_eq!(dfa.states().len(), 4);
+
+    // The keywords are shadowed by the identifiers
+    let (_, dfa) = build(&[mode("Initial", &["[a-z]+", "if|else|while"])]);
+    assert_eq!(dfa.states().len(), 2);
+
+    let (_, dfa) = build(&[mode("Initial", &["[a-c]x|[d-f]x|gx"])]);
+    assert_eq!(dfa.states().len(), 3);
+
+    // Xorshift, good enough to generate test inputs
+    let mut seed: u32 = 0x9e37_79b9;
+    let mut random = move |max: usize| {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+
+        seed as usize % max
+    };
+
+    let alphabet: Vec<char> = "abcefiwxz019 .\"\\ÉéαΩ".chars().collect();
+
+    let tests = vec![
+        vec![mode("Initial", &["a", "ab", "abc"])],
+        vec![mode("Initial", &["(a|b)*abb", "[ab]+"])],
+        vec![mode("Initial", &["if|else|while", "[a-z]+", "[0-9]+(\\.[0-9]+)?"])],
+        vec![mode("Initial", &["a{2,4}", "(ab?)+c", "[^ab]"])],
+        vec![mode("Initial", &["(?i)é+", "[à-ÿ]*z", "\\p{Greek}+"])],
+        vec![mode("Initial", &["\"", "[^\"]+"]),
+             mode("String", &["\"", "\\\\.", "[^\"\\\\]+"]),
+             ("Empty".to_owned(), Nfa::new_empty())],
+    ];
+
+    for modes in &tests {
+        let (full, minimal) = build(modes);
+
+        assert!(minimal.states().len() <= full.states().len());
+
+        for &(ref name, _) in modes {
+            let mut full_matcher = full.matcher();
+            let mut minimal_matcher = minimal.matcher();
+
+            full_matcher.set_mode(name);
+            minimal_matcher.set_mode(name);
+
+            for _ in 0..300 {
+                let len = random(12);
+                let input: String =
+                    (0..len).map(|_| alphabet[random(alphabet.len())]).collect();
+
+                assert_eq!(minimal_matcher.longest_match(&input),
+                           full_matcher.longest_match(&input),
+                           "{} on {:?}", name, input);
+            }
+        }
+    }
+}
@@ -2,3 +2,9 @@ pub mod nfa;
 pub mod dfa;
 pub mod codegen;
 pub mod character;
+pub mod regex;
+pub mod spec;
+pub mod build;
+pub mod runtime;
+
+mod unicode_tables;
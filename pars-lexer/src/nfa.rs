@@ -3,7 +3,7 @@
 use std::collections::{BTreeMap, VecDeque};
 use std::fmt;
 
-use character::Interval;
+use character::{Interval, IntervalSet};
 
 /// NFA state
 #[derive(Clone)]
@@ -93,6 +93,31 @@ impl Nfa {
         }
     }
 
+    /// Create a new NFA matching any character in `set`. If `set` is
+    /// empty the NFA never matches.
+    ///
+    /// ```text
+    ///       set[0]
+    /// (0) ----------> (f)
+    ///   \   set[n]     ^
+    ///    `-----------'
+    /// ```
+    pub fn new_set(set: &IntervalSet) -> Nfa {
+        let mut state = State::new();
+
+        for &i in set.intervals() {
+            state.set_moves(Input(i), vec![1]);
+        }
+
+        let mut states = VecDeque::new();
+
+        states.push_back(state);
+
+        Nfa {
+            states: states,
+        }
+    }
+
     /// Create a new NFA with a single accepting state having no
     /// transitions
     ///
@@ -252,6 +277,72 @@ impl Nfa {
         self.states.push_front(state_0);
     }
 
+    /// Make this NFA optional. `a.optional()` matches `a?`.
+    ///
+    /// ```text
+    ///        ε           a
+    /// (0) ------> (1) ------> (f)
+    ///  \                 ε     ^
+    ///   `--------------------'
+    /// ```
+    pub fn optional(&mut self) {
+        // Create state (0) pointing at (1) and (f)
+        let mut state_0 = State::new();
+
+        state_0.set_moves(Epsilon, vec![1, self.states.len() as isize + 1]);
+
+        self.states.push_front(state_0);
+    }
+
+    /// Repeat this NFA at least `min` and at most `max`
+    /// times. `a.repeat(2, 4)` matches `a{2,4}`.
+    ///
+    /// The optional repetitions are nested, `a{2,4}` is built as
+    /// `aa(a(a)?)?` instead of `aaa?a?` since it means that there's
+    /// only ever one way to match a given number of repetitions.
+    ///
+    /// Panics if `min > max`.
+    pub fn repeat(&mut self, min: usize, max: usize) {
+        assert!(min <= max);
+
+        let mut repeated = Nfa::new_empty();
+
+        for _ in 0..min {
+            repeated.concat(self.clone());
+        }
+
+        let mut optional = Nfa::new_empty();
+
+        for _ in min..max {
+            let mut o = self.clone();
+
+            o.concat(optional);
+            o.optional();
+
+            optional = o;
+        }
+
+        repeated.concat(optional);
+
+        *self = repeated;
+    }
+
+    /// Repeat this NFA at least `min` times. `a.repeat_at_least(2)`
+    /// matches `a{2,}` or `aa+`.
+    pub fn repeat_at_least(&mut self, min: usize) {
+        if min == 0 {
+            self.star();
+            return;
+        }
+
+        let mut last = self.clone();
+
+        last.positive();
+
+        self.repeat(min - 1, min - 1);
+        self.concat(last);
+    }
+
     /// Combines two NFAs by adding an ε-transition between the first
     /// state of `self` and the first state of `other`:
     ///
@@ -317,6 +408,162 @@ impl Nfa {
         self.states.append(&mut other.states);
     }
 
+    /// Make this NFA case-insensitive by expanding every input
+    /// interval with its Unicode simple case folding equivalents.
+    /// `a.fold_case()` matches `a`, `A`, `b.fold_case()` matches
+    /// `[bB]` and so on.
+    ///
+    /// This should be called on a rule before it's combined with
+    /// other rules, otherwise all the combined rules are affected.
+    pub fn fold_case(&mut self) {
+        for state in self.states.iter_mut() {
+            let mut folded_moves = BTreeMap::new();
+
+            for (&transition, targets) in state.move_map() {
+                match transition {
+                    Input(i) => {
+                        let folded = IntervalSet::from_interval(i).case_fold();
+
+                        for &f in folded.intervals() {
+                            let t = folded_moves.entry(Input(f))
+                                .or_insert(Vec::new());
+
+                            t.extend_from_slice(targets);
+                        }
+                    }
+                    Epsilon => {
+                        folded_moves.insert(Epsilon, targets.clone());
+                    }
+                }
+            }
+
+            for targets in folded_moves.values_mut() {
+                targets.sort();
+                targets.dedup();
+            }
+
+            state.moves = folded_moves;
+        }
+    }
+
+    /// Returns an equivalent NFA matching the UTF-8 encoding of the
+    /// input instead of Unicode code points. Each input interval is
+    /// replaced by sequences of byte intervals, for instance `[é-ê]`
+    /// becomes:
+    ///
+    /// ```text
+    ///       c3        a9-aa
+    /// (0) ------> (1) ------> (f)
+    /// ```
+    ///
+    /// A `Dfa` built from the resulting NFA runs on bytes and only
+    /// matches valid UTF-8.
+    pub fn to_utf8(&self) -> Nfa {
+        // Byte sequences for each input move of each state
+        let sequences: Vec<Vec<(Interval, Vec<Vec<Interval>>)>> =
+            self.states.iter().map(|state| {
+                state.move_map().keys()
+                    .filter_map(|&t| match t {
+                        Input(i) => Some((i, i.utf8_sequences())),
+                        Epsilon => None,
+                    })
+                    .collect()
+            }).collect();
+
+        // Each state is followed by the intermediate states of its
+        // multi-byte sequences, compute the new index of every state.
+        let mut new_index = Vec::with_capacity(self.states.len() + 1);
+        let mut len = 0;
+
+        for moves in &sequences {
+            new_index.push(len);
+
+            len += 1;
+
+            for (_, seqs) in moves {
+                len += seqs.iter().map(|s| s.len() - 1).sum::<usize>();
+            }
+        }
+
+        // One past the last state
+        new_index.push(len);
+
+        let mut states = VecDeque::with_capacity(len);
+
+        for (idx, (state, moves)) in self.states.iter().zip(&sequences).enumerate() {
+            let base = new_index[idx];
+
+            // Convert the relative targets of `state` into relative
+            // targets from the new state at `from`
+            let retarget = |targets: &[isize], from: usize| -> Vec<isize> {
+                targets.iter().map(|&t| {
+                    let old = (idx as isize + t) as usize;
+
+                    new_index[old] as isize - from as isize
+                }).collect()
+            };
+
+            let mut new_state =
+                match state.accepting() {
+                    Some(a) => State::new_accepting(a),
+                    None => State::new(),
+                };
+
+            let epsilon = state.get_moves(Epsilon);
+
+            if !epsilon.is_empty() {
+                new_state.set_moves(Epsilon, retarget(epsilon, base));
+            }
+
+            let mut intermediate: Vec<State> = Vec::new();
+
+            for &(i, ref seqs) in moves {
+                let targets = state.get_moves(Input(i));
+
+                for seq in seqs {
+                    let (last, head) = seq.split_last().unwrap();
+
+                    let mut from = base;
+
+                    for &b in head {
+                        let next = base + 1 + intermediate.len();
+
+                        if from == base {
+                            new_state.add_move(Input(b), (next - from) as isize);
+                        } else {
+                            intermediate[from - base - 1]
+                                .add_move(Input(b), (next - from) as isize);
+                        }
+
+                        intermediate.push(State::new());
+
+                        from = next;
+                    }
+
+                    let final_targets = retarget(targets, from);
+
+                    let s =
+                        if from == base {
+                            &mut new_state
+                        } else {
+                            &mut intermediate[from - base - 1]
+                        };
+
+                    for t in final_targets {
+                        s.add_move(Input(*last), t);
+                    }
+                }
+            }
+
+            states.push_back(new_state);
+            states.extend(intermediate);
+        }
+
+        Nfa {
+            states: states,
+        }
+    }
+
     /// Return true if `self` finishes with an accepting state.
     pub fn is_accepting(&self) -> bool {
         match self.states.back() {
@@ -325,6 +572,11 @@ impl Nfa {
         }
     }
 
+    /// Returns the number of states in this NFA
+    pub fn state_count(&self) -> usize {
+        self.states.len()
+    }
+
     /// Returns a `Vec` of states that are reachable from `states`
     /// using ε-transitions alone.
     pub fn epsilon_closure(&self, states: &[usize]) -> Vec<usize> {
@@ -431,6 +683,51 @@ impl Nfa {
             None
         }
     }
+
+    /// Run the NFA on `input` by tracking the set of states it can be
+    /// in after each character. Returns the length in bytes of the
+    /// longest non-empty match at the beginning of `input` and the
+    /// accepting state it ends in, or `None` if nothing matches.
+    ///
+    /// When several accepting states are reached at once the first
+    /// one wins, like in `Dfa::from_nfa`. This is much slower than
+    /// running the DFA but it's useful to check the DFA construction.
+    pub fn simulate(&self, input: &str) -> Option<(usize, String)> {
+        if self.states.is_empty() {
+            return None;
+        }
+
+        let mut states = self.epsilon_closure(&[0]);
+        let mut longest = None;
+
+        for (pos, c) in input.char_indices() {
+            let mut next = Vec::new();
+
+            for &s in &states {
+                for &transition in self.states[s].move_map().keys() {
+                    if let Input(i) = transition {
+                        if i.first() <= c as u32 && c as u32 <= i.last() {
+                            next.extend(self.transitions(s, transition));
+                        }
+                    }
+                }
+            }
+
+            if next.is_empty() {
+                break;
+            }
+
+            states = self.epsilon_closure(&next);
+            states.sort();
+            states.dedup();
+
+            if let Some(a) = states.iter().filter_map(|&s| self.accepting(s)).next() {
+                longest = Some((pos + c.len_utf8(), a));
+            }
+        }
+
+        longest
+    }
 }
 
 impl fmt::Debug for Nfa {
@@ -472,3 +769,211 @@ impl fmt::Debug for Transition {
         }
     }
 }
+
+#[cfg(test)]
+fn dfa_states(mut nfa: Nfa) -> usize {
+    use dfa::Dfa;
+
+    nfa.concat(Nfa::new_accepting("match".into()));
+
+    Dfa::from_nfa(&nfa).states().len()
+}
+
+#[test]
+fn repetition() {
+    let a = Nfa::new(Interval::new_single('a'));
+    let mut hex = Nfa::new(Interval::new('0', '9'));
+    hex.union(Nfa::new(Interval::new('a', 'f')));
+
+    let mut ab = a.clone();
+    ab.concat(Nfa::new(Interval::new_single('b')));
+
+    let repeat = |nfa: &Nfa, min, max| {
+        let mut nfa = nfa.clone();
+        nfa.repeat(min, max);
+        nfa
+    };
+
+    let at_least = |nfa: &Nfa, min| {
+        let mut nfa = nfa.clone();
+        nfa.repeat_at_least(min);
+        nfa
+    };
+
+    let mut optional = a.clone();
+    optional.optional();
+
+    // The first expected value is the number of states of the NFA.
+    // The mandatory repetitions are plain copies of the fragment and
+    // each optional one only adds a single state, so `x{min,max}`
+    // takes `min * n + (max - min) * (n + 1)` states for a fragment
+    // of `n` states. The second one is the number of states of the
+    // minimal DFA.
+    let states = |nfa: &Nfa| (nfa.state_count(), dfa_states(nfa.clone()));
+
+    // a, [0-9a-f], ab
+    assert_eq!(states(&a), (1, 2));
+    assert_eq!(states(&hex), (5, 2));
+    assert_eq!(states(&ab), (2, 3));
+
+    // a?
+    assert_eq!(states(&optional), (2, 2));
+    // a{3}
+    assert_eq!(states(&repeat(&a, 3, 3)), (3, 4));
+    // a{4}
+    assert_eq!(states(&repeat(&a, 4, 4)), (4, 5));
+    // [0-9a-f]{4}
+    assert_eq!(states(&repeat(&hex, 4, 4)), (20, 5));
+    // a{2,4}
+    assert_eq!(states(&repeat(&a, 2, 4)), (6, 5));
+    // a{0,3}
+    assert_eq!(states(&repeat(&a, 0, 3)), (6, 4));
+    // (ab){1,3}
+    assert_eq!(states(&repeat(&ab, 1, 3)), (8, 7));
+    // a{0}
+    assert_eq!(states(&repeat(&a, 0, 0)), (0, 1));
+    // a{2,}, that is aa+
+    assert_eq!(states(&at_least(&a, 2)), (4, 3));
+    // a{3,}, that is aaa+
+    assert_eq!(states(&at_least(&a, 3)), (5, 4));
+    // a{0,}, that is a*
+    assert_eq!(states(&at_least(&a, 0)), (3, 1));
+    // (ab){2,}, that is ab(ab)+
+    assert_eq!(states(&at_least(&ab, 2)), (6, 5));
+    // a{1000}
+    assert_eq!(states(&repeat(&a, 1000, 1000)), (1000, 1001));
+}
+
+#[test]
+fn utf8() {
+    use dfa::Dfa;
+
+    // Run the DFA built from `nfa` on `input`, returns `true` if it
+    // ends up in an accepting state
+    fn matches(nfa: &Nfa, input: &[u8]) -> bool {
+        let dfa = Dfa::from_nfa(nfa);
+
+        let mut state = 0;
+
+        for &b in input {
+            state =
+                match dfa.states()[state].target(b as u32) {
+                    Some(s) => s,
+                    None => return false,
+                };
+        }
+
+        dfa.states()[state].is_accepting()
+    }
+
+    // [a-zé-ê]+(€|😀)?
+    let mut nfa = Nfa::new(Interval::new('a', 'z'));
+    nfa.union(Nfa::new(Interval::new('é', 'ê')));
+    nfa.positive();
+
+    let mut symbol = Nfa::new(Interval::new_single('€'));
+    symbol.union(Nfa::new(Interval::new_single('😀')));
+    symbol.optional();
+
+    nfa.concat(symbol);
+    nfa.concat(Nfa::new_accepting("match".into()));
+
+    let utf8 = nfa.to_utf8();
+
+    for s in &["a", "été", "zê€", "e😀"] {
+        assert!(matches(&utf8, s.as_bytes()));
+    }
+
+    for s in &["", "A", "è", "€", "a€€"] {
+        assert!(!matches(&utf8, s.as_bytes()));
+    }
+
+    // Invalid UTF-8 never matches
+    assert!(!matches(&utf8, b"\xc3"));
+    assert!(!matches(&utf8, b"\xc3\xc3\xa9"));
+    assert!(!matches(&utf8, b"a\xe2\x82"));
+
+    // Any scalar value
+    let mut any = Nfa::new_set(&IntervalSet::any());
+    any.concat(Nfa::new_accepting("match".into()));
+
+    let any = any.to_utf8();
+
+    for s in &["\0", "\u{7f}", "\u{80}", "\u{d7ff}", "\u{e000}", "\u{10ffff}"] {
+        assert!(matches(&any, s.as_bytes()));
+    }
+
+    // Surrogate
+    assert!(!matches(&any, b"\xed\xa0\x80"));
+}
+
+#[test]
+fn simulation() {
+    use dfa::Dfa;
+    use regex;
+
+    // Xorshift, good enough to generate test inputs
+    let mut seed: u32 = 0x2545_f491;
+    let mut random = move |max: usize| {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+
+        seed as usize % max
+    };
+
+    let rule_sets: &[&[&str]] = &[
+        &["a", "ab", "abc"],
+        &["(a|b)*abb", "[ab]+"],
+        &["if", "[a-z]+", "[0-9]+(\\.[0-9]+)?", "[ .]+"],
+        &["a{2,4}", "(ab?)+c", "[^ab]"],
+        &["(?i)é+", "[à-ÿ]*z", "\\p{Greek}+"],
+        &["\"([^\"\\\\]|\\\\.)*\"", "\\\\"],
+    ];
+
+    let alphabet: Vec<char> = "abcz019 .\"\\ÉéàαΩ".chars().collect();
+
+    for rules in rule_sets {
+        let mut nfa = Nfa::new_empty();
+
+        for (i, pattern) in rules.iter().enumerate() {
+            let mut rule = regex::parse(pattern).unwrap();
+            rule.concat(Nfa::new_accepting(format!("rule {}", i)));
+
+            if i == 0 {
+                nfa = rule;
+            } else {
+                nfa.combine(rule);
+            }
+        }
+
+        let dfa = Dfa::from_nfa(&nfa);
+        let matcher = dfa.matcher();
+        let mut matches = 0;
+
+        for _ in 0..500 {
+            let len = random(12);
+            let input: String =
+                (0..len).map(|_| alphabet[random(alphabet.len())]).collect();
+
+            // Try every suffix, like a lexer would
+            for (start, _) in input.char_indices() {
+                let input = &input[start..];
+
+                let expected = nfa.simulate(input);
+                let found = matcher.longest_match(input)
+                    .map(|(rule, len)| (len, rule.to_owned()));
+
+                assert_eq!(found, expected, "{:?} on {:?}", rules, input);
+
+                if found.is_some() {
+                    matches += 1;
+                }
+            }
+        }
+
+        // Make sure we're not just comparing failures
+        assert!(matches > 100, "{:?}: {} matches", rules, matches);
+    }
+}
+
@@ -0,0 +1,896 @@
+//! Regular expression front-end. Parses a pattern string into an
+//! `Nfa`.
+//!
+//! The supported syntax is a conventional subset of what most regex
+//! engines understand:
+//!
+//! ```text
+//! a         the literal character 'a'
+//! .         any character except '\n'
+//! [a-z_]    any character in the class
+//! [^"\\]    any character not in the class
+//! ab        'a' followed by 'b'
+//! a|b       'a' or 'b'
+//! a*        zero or more 'a'
+//! a+        one or more 'a'
+//! a?        zero or one 'a'
+//! a{3}      exactly three 'a'
+//! a{3,}     at least three 'a'
+//! a{3,5}    between three and five 'a'
+//! (ab)      grouping
+//! (?i)      case-insensitive matching for the rest of the group
+//! (?i:ab)   case-insensitive matching within the group
+//! (?-i)     turn case-insensitive matching off
+//! \n \r \t  newline, carriage return and tab
+//! \d        [0-9]
+//! \w        [0-9A-Za-z_]
+//! \s        [ \t\n\r\x0b\x0c]
+//! \D \W \S  complement of \d, \w and \s respectively
+//! \p{Greek} any character with the given Unicode property, see
+//!           `IntervalSet::unicode_property` for the supported names
+//! \pL       one-letter general category, same as \p{L}
+//! \P{Greek} any character without the given Unicode property
+//! \x7f      character with hexadecimal code 7f
+//! \u{430}   character with hexadecimal code 430
+//! \*        any other escaped punctuation matches itself
+//! ```
+//!
+//! When parsed with `parse_with_definitions` a pattern can also
+//! reference previously defined patterns by name, `{DIGIT}+` matches
+//! one or more repetitions of the definition named `DIGIT`.
+//!
+//! Repetition counts can't exceed `MAX_REPETITION` since every
+//! repetition copies the repeated pattern. Nested repetitions are
+//! also limited: a single repetition can't expand its pattern to
+//! more than `MAX_REPETITION_STATES` NFA states.
+
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+
+use character::{Interval, IntervalSet};
+use nfa::Nfa;
+
+/// Largest count allowed in a `{min,max}` repetition
+pub const MAX_REPETITION: usize = 1000;
+
+/// Largest number of NFA states a `{min,max}` repetition can expand
+/// its pattern to, so that `(a{1000}){1000}` is rejected
+pub const MAX_REPETITION_STATES: usize = 10_000;
+
+/// Parse `pattern` and return the corresponding NFA.
+///
+/// The returned NFA is not accepting, the caller is expected to
+/// concatenate it with an accepting state before combining it with
+/// other rules:
+///
+/// ```rust
+/// use pars_lexer::nfa::Nfa;
+/// use pars_lexer::regex;
+///
+/// let mut id = regex::parse("[a-zA-Z_][a-zA-Z_0-9]*").unwrap();
+/// id.concat(Nfa::new_accepting("identifier".into()));
+/// ```
+pub fn parse(pattern: &str) -> Result<Nfa, Error> {
+    Parser::new(pattern, None).parse()
+}
+
+/// Parse `pattern` like `parse` but allow `{NAME}` references to the
+/// NFAs in `definitions`.
+///
+/// ```rust
+/// use std::collections::BTreeMap;
+///
+/// use pars_lexer::regex;
+///
+/// let mut definitions = BTreeMap::new();
+///
+/// definitions.insert("DIGIT".to_owned(), regex::parse("[0-9]").unwrap());
+///
+/// let number = regex::parse_with_definitions("{DIGIT}+(\\.{DIGIT}+)?",
+///                                            &definitions).unwrap();
+/// ```
+pub fn parse_with_definitions(pattern: &str,
+                              definitions: &BTreeMap<String, Nfa>)
+                              -> Result<Nfa, Error> {
+    Parser::new(pattern, Some(definitions)).parse()
+}
+
+/// Recursive descent parser for the pattern syntax
+struct Parser<'d> {
+    chars: Vec<char>,
+    /// Index of the next character to be parsed in `chars`
+    pos: usize,
+    /// `true` if the `i` flag is currently set
+    case_insensitive: bool,
+    /// Named patterns that can be referenced using `{NAME}`. If
+    /// `None` then `{` is a normal character.
+    definitions: Option<&'d BTreeMap<String, Nfa>>,
+}
+
+impl<'d> Parser<'d> {
+    fn new(pattern: &str,
+           definitions: Option<&'d BTreeMap<String, Nfa>>) -> Parser<'d> {
+        Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            case_insensitive: false,
+            definitions: definitions,
+        }
+    }
+
+    fn parse(&mut self) -> Result<Nfa, Error> {
+        let nfa = try!(self.parse_alternation());
+
+        match self.peek() {
+            None => Ok(nfa),
+            // `parse_alternation` only stops early on a closing
+            // parenthesis
+            Some(_) => Err(self.error(ErrorKind::UnmatchedParen)),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+
+        if c.is_some() {
+            self.pos += 1;
+        }
+
+        c
+    }
+
+    /// Build an error pointing at the character at `self.pos`
+    fn error(&self, kind: ErrorKind) -> Error {
+        self.error_at(self.pos, kind)
+    }
+
+    fn error_at(&self, pos: usize, kind: ErrorKind) -> Error {
+        Error {
+            column: pos + 1,
+            kind: kind,
+        }
+    }
+
+    /// `alternation := concatenation ('|' concatenation)*`
+    fn parse_alternation(&mut self) -> Result<Nfa, Error> {
+        let mut nfa = try!(self.parse_concatenation());
+
+        while self.peek() == Some('|') {
+            self.next();
+
+            let other = try!(self.parse_concatenation());
+
+            nfa.union(other);
+        }
+
+        Ok(nfa)
+    }
+
+    /// `concatenation := repetition*`
+    fn parse_concatenation(&mut self) -> Result<Nfa, Error> {
+        let mut nfa = Nfa::new_empty();
+
+        loop {
+            match self.peek() {
+                None | Some('|') | Some(')') => break,
+                _ => {
+                    let r = try!(self.parse_repetition());
+
+                    nfa.concat(r);
+                }
+            }
+        }
+
+        Ok(nfa)
+    }
+
+    /// `repetition := atom ('*' | '+' | '?' | '{' count '}')*`
+    fn parse_repetition(&mut self) -> Result<Nfa, Error> {
+        let mut nfa = try!(self.parse_atom());
+
+        loop {
+            match self.peek() {
+                Some('*') => nfa.star(),
+                Some('+') => nfa.positive(),
+                Some('?') => nfa.optional(),
+                Some('{') if self.is_counted_repetition() => {
+                    let start = self.pos;
+
+                    let (min, max) = try!(self.parse_count());
+
+                    let count = max.unwrap_or(min);
+
+                    if nfa.state_count() * count > MAX_REPETITION_STATES {
+                        return Err(self.error_at(start,
+                                                 ErrorKind::RepetitionTooLarge));
+                    }
+
+                    match max {
+                        Some(max) => nfa.repeat(min, max),
+                        None => nfa.repeat_at_least(min),
+                    }
+
+                    continue;
+                }
+                _ => break,
+            }
+
+            self.next();
+        }
+
+        Ok(nfa)
+    }
+
+    /// Returns `true` if the `{` at the current position starts a
+    /// counted repetition, that is if it's followed by a digit.
+    fn is_counted_repetition(&self) -> bool {
+        self.chars.get(self.pos + 1).map_or(false, |c| c.is_ascii_digit())
+    }
+
+    /// Parse a `{min}`, `{min,}` or `{min,max}` repetition count
+    fn parse_count(&mut self) -> Result<(usize, Option<usize>), Error> {
+        let start = self.pos;
+
+        // Skip the '{'
+        self.next();
+
+        let min = try!(self.parse_number(start));
+
+        let max =
+            match self.next() {
+                Some('}') => return Ok((min, Some(min))),
+                Some(',') if self.peek() == Some('}') => None,
+                Some(',') => Some(try!(self.parse_number(start))),
+                _ => return Err(self.error_at(start,
+                                              ErrorKind::InvalidRepetition)),
+            };
+
+        if self.next() != Some('}') {
+            return Err(self.error_at(start, ErrorKind::InvalidRepetition));
+        }
+
+        match max {
+            Some(max) if max < min =>
+                Err(self.error_at(start, ErrorKind::InvalidRepetition)),
+            _ => Ok((min, max)),
+        }
+    }
+
+    fn parse_number(&mut self, start: usize) -> Result<usize, Error> {
+        let mut n: usize = 0;
+        let mut digits = 0;
+
+        while let Some(d) = self.peek().and_then(|c| c.to_digit(10)) {
+            self.next();
+
+            n = n * 10 + d as usize;
+
+            if n > MAX_REPETITION {
+                return Err(self.error_at(start, ErrorKind::InvalidRepetition));
+            }
+
+            digits += 1;
+        }
+
+        if digits == 0 {
+            return Err(self.error_at(start, ErrorKind::InvalidRepetition));
+        }
+
+        Ok(n)
+    }
+
+    fn parse_atom(&mut self) -> Result<Nfa, Error> {
+        let start = self.pos;
+
+        let c =
+            match self.next() {
+                Some(c) => c,
+                None => return Err(self.error(ErrorKind::UnexpectedEnd)),
+            };
+
+        match c {
+            '(' => {
+                // Flags are scoped to the group they're set in
+                let case_insensitive = self.case_insensitive;
+
+                if self.peek() == Some('?') {
+                    self.next();
+
+                    if !try!(self.parse_flags(start)) {
+                        // `(?i)`: the flags apply to the rest of the
+                        // enclosing group and there's nothing to
+                        // match.
+                        return Ok(Nfa::new_empty());
+                    }
+                }
+
+                let nfa = try!(self.parse_alternation());
+
+                self.case_insensitive = case_insensitive;
+
+                match self.next() {
+                    Some(')') => Ok(nfa),
+                    _ => Err(self.error_at(start, ErrorKind::UnclosedGroup)),
+                }
+            }
+            '[' => {
+                let set = try!(self.parse_class(start));
+
+                Ok(Nfa::new_set(&set))
+            }
+            '.' => {
+                let newline = IntervalSet::from_interval(
+                    Interval::new_single('\n'));
+
+                Ok(Nfa::new_set(&self.fold(newline.complement())))
+            }
+            '\\' => {
+                let set = try!(self.parse_escape(start));
+
+                Ok(Nfa::new_set(&self.fold(set)))
+            }
+            '{' if self.definitions.is_some() =>
+                self.parse_reference(start),
+            '*' | '+' | '?' =>
+                Err(self.error_at(start, ErrorKind::MissingRepeatOperand)),
+            c => Ok(Nfa::new_set(&self.fold(single(c)))),
+        }
+    }
+
+    /// Parse a character class. The opening `[` has already been
+    /// consumed, `start` is its position.
+    fn parse_class(&mut self, start: usize) -> Result<IntervalSet, Error> {
+        let mut intervals = Vec::new();
+
+        let negated = self.peek() == Some('^');
+
+        if negated {
+            self.next();
+        }
+
+        loop {
+            let item_start = self.pos;
+
+            let first =
+                match self.next() {
+                    None => return Err(self.error_at(start,
+                                                     ErrorKind::UnclosedClass)),
+                    Some(']') => break,
+                    Some('\\') => try!(self.parse_escape(item_start)),
+                    Some(c) => single(c),
+                };
+
+            // A '-' is only a range operator if it's between two
+            // single characters, otherwise it's a literal '-'.
+            let is_range =
+                single_char(&first).is_some() &&
+                self.peek() == Some('-') &&
+                self.chars.get(self.pos + 1).map_or(false, |&c| c != ']');
+
+            if !is_range {
+                intervals.extend_from_slice(first.intervals());
+                continue;
+            }
+
+            // Skip the '-'
+            self.next();
+
+            let last_start = self.pos;
+
+            let last =
+                match self.next() {
+                    Some('\\') => try!(self.parse_escape(last_start)),
+                    Some(c) => single(c),
+                    None => unreachable!(),
+                };
+
+            let first = single_char(&first).unwrap();
+
+            let last =
+                match single_char(&last) {
+                    Some(c) => c,
+                    None => return Err(self.error_at(
+                        last_start, ErrorKind::InvalidRangeBound)),
+                };
+
+            if first > last {
+                return Err(self.error_at(item_start, ErrorKind::InvalidRange));
+            }
+
+            intervals.push(Interval::new(first, last));
+        }
+
+        let mut set = IntervalSet::from_intervals(intervals);
+
+        if set.is_empty() {
+            return Err(self.error_at(start, ErrorKind::EmptyClass));
+        }
+
+        // Fold before negating so that `(?i)[^a]` excludes both `a`
+        // and `A`
+        set = self.fold(set);
+
+        if negated {
+            set = set.complement();
+        }
+
+        Ok(set)
+    }
+
+    /// Parse a `{NAME}` reference to a definition. The opening `{` has
+    /// already been consumed, `start` is its position.
+    fn parse_reference(&mut self, start: usize) -> Result<Nfa, Error> {
+        let mut name = String::new();
+
+        loop {
+            match self.next() {
+                Some('}') => break,
+                Some(c) => name.push(c),
+                None => return Err(self.error_at(start,
+                                                 ErrorKind::UnclosedReference)),
+            }
+        }
+
+        let definition =
+            self.definitions.and_then(|d| d.get(&name));
+
+        match definition {
+            Some(nfa) => {
+                let mut nfa = nfa.clone();
+
+                if self.case_insensitive {
+                    nfa.fold_case();
+                }
+
+                Ok(nfa)
+            }
+            None => Err(self.error_at(start, ErrorKind::UndefinedName)),
+        }
+    }
+
+    /// Parse the flags of a `(?flags)` or `(?flags:...)` group. The
+    /// leading `(?` has already been consumed, `start` is the
+    /// position of the parenthesis. Returns `true` if the flags are
+    /// followed by a sub-pattern.
+    fn parse_flags(&mut self, start: usize) -> Result<bool, Error> {
+        let mut enable = true;
+
+        loop {
+            match self.next() {
+                Some('i') => self.case_insensitive = enable,
+                Some('-') if enable => enable = false,
+                Some(':') => return Ok(true),
+                Some(')') => return Ok(false),
+                Some(_) => return Err(self.error_at(self.pos - 1,
+                                                    ErrorKind::UnknownFlag)),
+                None => return Err(self.error_at(start,
+                                                 ErrorKind::UnclosedGroup)),
+            }
+        }
+    }
+
+    /// Apply case folding to `set` if the `i` flag is set
+    fn fold(&self, set: IntervalSet) -> IntervalSet {
+        if self.case_insensitive {
+            set.case_fold()
+        } else {
+            set
+        }
+    }
+
+    /// Parse an escape sequence. The backslash has already been
+    /// consumed, `start` is its position.
+    fn parse_escape(&mut self, start: usize) -> Result<IntervalSet, Error> {
+        let c =
+            match self.next() {
+                Some(c) => c,
+                None => return Err(self.error_at(start,
+                                                 ErrorKind::TrailingBackslash)),
+            };
+
+        match c {
+            'n' => Ok(single('\n')),
+            'r' => Ok(single('\r')),
+            't' => Ok(single('\t')),
+            'd' => Ok(digit()),
+            'w' => Ok(word()),
+            's' => Ok(space()),
+            'D' => Ok(digit().complement()),
+            'W' => Ok(word().complement()),
+            'S' => Ok(space().complement()),
+            'p' => self.parse_property(start),
+            'P' => Ok(try!(self.parse_property(start)).complement()),
+            'x' => {
+                let mut code = 0;
+
+                for _ in 0..2 {
+                    code = code * 16 + try!(self.parse_hex_digit(start));
+                }
+
+                Ok(single(try!(self.code_to_char(code, start))))
+            }
+            'u' => {
+                if self.next() != Some('{') {
+                    return Err(self.error_at(start, ErrorKind::InvalidEscape));
+                }
+
+                let mut code = 0u32;
+                let mut digits = 0;
+
+                while self.peek() != Some('}') {
+                    code = code * 16 + try!(self.parse_hex_digit(start));
+                    digits += 1;
+
+                    if digits > 6 {
+                        return Err(self.error_at(start,
+                                                 ErrorKind::InvalidCodePoint));
+                    }
+                }
+
+                // Skip the '}'
+                self.next();
+
+                if digits == 0 {
+                    return Err(self.error_at(start, ErrorKind::InvalidEscape));
+                }
+
+                Ok(single(try!(self.code_to_char(code, start))))
+            }
+            c if c.is_ascii() && !c.is_alphanumeric() => Ok(single(c)),
+            _ => Err(self.error_at(start, ErrorKind::InvalidEscape)),
+        }
+    }
+
+    /// Parse the name of the property following `\p` or `\P`, either
+    /// a single letter or a name between braces.
+    fn parse_property(&mut self, start: usize) -> Result<IntervalSet, Error> {
+        let name =
+            match self.next() {
+                Some('{') => {
+                    let mut name = String::new();
+
+                    loop {
+                        match self.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(self.error_at(
+                                start, ErrorKind::InvalidEscape)),
+                        }
+                    }
+
+                    name
+                }
+                Some(c) if c.is_alphabetic() => c.to_string(),
+                _ => return Err(self.error_at(start, ErrorKind::InvalidEscape)),
+            };
+
+        match IntervalSet::unicode_property(&name) {
+            Some(set) => Ok(set),
+            None => Err(self.error_at(start, ErrorKind::UnknownProperty)),
+        }
+    }
+
+    fn parse_hex_digit(&mut self, escape_start: usize) -> Result<u32, Error> {
+        match self.next().and_then(|c| c.to_digit(16)) {
+            Some(d) => Ok(d),
+            None => Err(self.error_at(escape_start, ErrorKind::InvalidEscape)),
+        }
+    }
+
+    fn code_to_char(&self, code: u32, escape_start: usize) -> Result<char, Error> {
+        match ::std::char::from_u32(code) {
+            Some(c) => Ok(c),
+            None => Err(self.error_at(escape_start,
+                                      ErrorKind::InvalidCodePoint)),
+        }
+    }
+}
+
+fn single(c: char) -> IntervalSet {
+    IntervalSet::from_interval(Interval::new_single(c))
+}
+
+/// If `set` contains exactly one character return it
+fn single_char(set: &IntervalSet) -> Option<char> {
+    match set.intervals() {
+        [i] if i.first() == i.last() => ::std::char::from_u32(i.first()),
+        _ => None,
+    }
+}
+
+/// `\d`
+fn digit() -> IntervalSet {
+    IntervalSet::from_interval(Interval::new('0', '9'))
+}
+
+/// `\w`
+fn word() -> IntervalSet {
+    IntervalSet::from_intervals(vec![
+        Interval::new('0', '9'),
+        Interval::new('A', 'Z'),
+        Interval::new_single('_'),
+        Interval::new('a', 'z'),
+    ])
+}
+
+/// `\s`
+fn space() -> IntervalSet {
+    IntervalSet::from_intervals(vec![
+        Interval::new('\t', '\r'),
+        Interval::new_single(' '),
+    ])
+}
+
+/// Error returned when a pattern fails to parse
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    /// Column of the offending character in the pattern, starting
+    /// at 1
+    column: usize,
+    kind: ErrorKind,
+}
+
+impl Error {
+    /// Column of the offending character in the pattern. The first
+    /// character is at column 1.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "column {}: {}", self.column, self.kind)
+    }
+}
+
+impl error::Error for Error {
+}
+
+/// The various pattern errors
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The pattern ended unexpectedly
+    UnexpectedEnd,
+    /// `(` without a matching `)`
+    UnclosedGroup,
+    /// `)` without a matching `(`
+    UnmatchedParen,
+    /// `[` without a matching `]`
+    UnclosedClass,
+    /// `[]`
+    EmptyClass,
+    /// Range whose start is greater than its end, such as `[z-a]`
+    InvalidRange,
+    /// Range bounded by a multi-character escape, such as `[a-\d]`
+    InvalidRangeBound,
+    /// Repetition operator with nothing to repeat, such as `*a`
+    MissingRepeatOperand,
+    /// Unknown or malformed escape sequence
+    InvalidEscape,
+    /// Escaped code point that isn't a valid `char`
+    InvalidCodePoint,
+    /// Backslash at the end of the pattern
+    TrailingBackslash,
+    /// Unknown Unicode property name in `\p{...}`
+    UnknownProperty,
+    /// Unknown flag in `(?...)`
+    UnknownFlag,
+    /// Malformed `{min,max}` repetition, `min > max` or a count
+    /// above `MAX_REPETITION`
+    InvalidRepetition,
+    /// Repetition expanding its pattern to more than
+    /// `MAX_REPETITION_STATES` NFA states, such as `(a{1000}){1000}`
+    RepetitionTooLarge,
+    /// `{NAME}` without a closing `}`
+    UnclosedReference,
+    /// `{NAME}` references an unknown definition
+    UndefinedName,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s =
+            match *self {
+                ErrorKind::UnexpectedEnd => "unexpected end of pattern",
+                ErrorKind::UnclosedGroup => "unclosed group",
+                ErrorKind::UnmatchedParen => "unmatched ')'",
+                ErrorKind::UnclosedClass => "unclosed character class",
+                ErrorKind::EmptyClass => "empty character class",
+                ErrorKind::InvalidRange => "invalid character range",
+                ErrorKind::InvalidRangeBound =>
+                    "character range bound must be a single character",
+                ErrorKind::MissingRepeatOperand =>
+                    "repetition operator without operand",
+                ErrorKind::InvalidEscape => "invalid escape sequence",
+                ErrorKind::InvalidCodePoint => "invalid code point",
+                ErrorKind::TrailingBackslash => "trailing backslash",
+                ErrorKind::UnknownProperty => "unknown Unicode property",
+                ErrorKind::UnknownFlag => "unknown flag",
+                ErrorKind::InvalidRepetition => "invalid repetition count",
+                ErrorKind::RepetitionTooLarge => "repetition too large",
+                ErrorKind::UnclosedReference => "unclosed definition reference",
+                ErrorKind::UndefinedName => "undefined name",
+            };
+
+        write!(f, "{}", s)
+    }
+}
+
+#[cfg(test)]
+fn matches(pattern: &str, s: &str) -> bool {
+    let mut nfa = parse(pattern).unwrap();
+    nfa.concat(Nfa::new_accepting("match".into()));
+
+    dfa_matches(&nfa, s)
+}
+
+#[cfg(test)]
+fn dfa_matches(nfa: &Nfa, s: &str) -> bool {
+    use dfa::Dfa;
+
+    let dfa = Dfa::from_nfa(nfa);
+    let states = dfa.states();
+
+    let mut state = 0;
+
+    for c in s.chars() {
+        let next =
+            states[state].move_map().iter()
+            .find(|&(i, _)| i.first() <= c as u32 && c as u32 <= i.last())
+            .map(|(_, &t)| t);
+
+        match next {
+            Some(n) => state = n,
+            None => return false,
+        }
+    }
+
+    states[state].is_accepting()
+}
+
+#[test]
+fn syntax() {
+    assert!(matches("abc", "abc"));
+    assert!(!matches("abc", "ab"));
+
+    assert!(matches("a|bc", "a"));
+    assert!(matches("a|bc", "bc"));
+    assert!(!matches("a|bc", "ac"));
+
+    assert!(matches("(a|b)*abb", "babbababb"));
+    assert!(!matches("(a|b)*abb", "babbabab"));
+
+    assert!(matches("ab+c?", "abbb"));
+    assert!(matches("ab+c?", "abc"));
+    assert!(!matches("ab+c?", "ac"));
+
+    assert!(matches("[a-zA-Z_][a-zA-Z_0-9]*", "_AbC12"));
+    assert!(!matches("[a-zA-Z_][a-zA-Z_0-9]*", "0invalid"));
+
+    assert!(matches("[-+]?\\d+", "-42"));
+    assert!(matches("[a-]+", "a-a"));
+    assert!(matches("\\w+\\s\\w", "foo_0\tb"));
+    assert!(matches("\\(\\.\\)", "(.)"));
+    assert!(matches("\\x41\\u{430}", "Aа"));
+
+    assert!(matches("\"([^\"\\\\]|\\\\.)*\"", "\"a \\\"quote\\\"\""));
+    assert!(!matches("\"([^\"\\\\]|\\\\.)*\"", "\"a \"quote\"\""));
+    assert!(matches("[^a-z]+", "ABC012"));
+    assert!(!matches("[^a-z]+", "AbC"));
+    assert!(matches("[^\\d\\s]+", "ab_?"));
+    assert!(!matches("[^\\d\\s]+", "a b"));
+    assert!(matches("\\D\\W\\S", "a.b"));
+    assert!(!matches("\\D", "7"));
+
+    assert!(matches("\\p{XID_Start}\\p{XID_Continue}*", "привет_2"));
+    assert!(!matches("\\p{XID_Start}\\p{XID_Continue}*", "_привет"));
+    assert!(matches("\\pL+", "λόγος"));
+    assert!(matches("[\\p{Greek}\\p{Nd}]+", "λ٣7"));
+    assert!(!matches("\\P{Greek}", "λ"));
+    assert!(matches("[^\\p{Lu}]", "λ"));
+
+    assert!(matches("(?i)select", "SeLeCt"));
+    assert!(matches("(?i)[a-c]+", "aBc"));
+    assert!(matches("(?i)[^a]", "b"));
+    assert!(!matches("(?i)[^a]", "A"));
+    assert!(matches("a(?i:b)c", "aBc"));
+    assert!(!matches("a(?i:b)c", "aBC"));
+    assert!(!matches("(a(?i)b)c", "aBC"));
+    assert!(matches("(?i)a(?-i)b", "Ab"));
+    assert!(!matches("(?i)a(?-i)b", "AB"));
+    assert!(matches("(?i)\\u{212a}", "k"));
+
+    assert!(matches("\\\\u[0-9a-fA-F]{4}", "\\u00e9"));
+    assert!(!matches("\\\\u[0-9a-fA-F]{4}", "\\u0e9"));
+    assert!(!matches("\\\\u[0-9a-fA-F]{4}", "\\u00e9a"));
+    assert!(matches("\\d+(e[-+]?\\d+)?", "10e-3"));
+    assert!(matches("a{2,}", "aaaaa"));
+    assert!(!matches("a{2,}", "a"));
+    assert!(matches("(ab){1,2}c", "ababc"));
+    assert!(!matches("(ab){1,2}c", "abababc"));
+    assert!(matches("a{0}b", "b"));
+    assert!(matches("a{,}", "a{,}"));
+
+    assert!(matches("a.c", "abc"));
+    assert!(matches("a.c", "aпc"));
+    assert!(!matches("a.c", "a\nc"));
+}
+
+#[test]
+fn errors() {
+    let error = |pattern| {
+        let e = parse(pattern).err().unwrap();
+        (e.column(), e.kind())
+    };
+
+    assert_eq!(error("ab(c"), (3, ErrorKind::UnclosedGroup));
+    assert_eq!(error("abc)"), (4, ErrorKind::UnmatchedParen));
+    assert_eq!(error("a[bc"), (2, ErrorKind::UnclosedClass));
+    assert_eq!(error("a[]"), (2, ErrorKind::EmptyClass));
+    assert_eq!(error("[z-a]"), (2, ErrorKind::InvalidRange));
+    assert_eq!(error("[a-\\d]"), (4, ErrorKind::InvalidRangeBound));
+    assert_eq!(error("a|*"), (3, ErrorKind::MissingRepeatOperand));
+    assert_eq!(error("ab\\q"), (3, ErrorKind::InvalidEscape));
+    assert_eq!(error("\\u{110000}"), (1, ErrorKind::InvalidCodePoint));
+    assert_eq!(error("ab\\"), (3, ErrorKind::TrailingBackslash));
+    assert_eq!(error("a\\p{Klingon}"), (2, ErrorKind::UnknownProperty));
+    assert_eq!(error("a\\p{L"), (2, ErrorKind::InvalidEscape));
+    assert_eq!(error("(?x)"), (3, ErrorKind::UnknownFlag));
+    assert_eq!(error("a(?i"), (2, ErrorKind::UnclosedGroup));
+    assert_eq!(error("ab{3,2}"), (3, ErrorKind::InvalidRepetition));
+    assert_eq!(error("ab{3"), (3, ErrorKind::InvalidRepetition));
+    assert_eq!(error("ab{3,x}"), (3, ErrorKind::InvalidRepetition));
+    assert_eq!(error("a{1001}"), (2, ErrorKind::InvalidRepetition));
+    assert_eq!(error("a{2,1001}"), (2, ErrorKind::InvalidRepetition));
+    assert_eq!(error("a{99999999999999999999999}"),
+               (2, ErrorKind::InvalidRepetition));
+    assert!(parse("a{1000}").is_ok());
+    assert_eq!(error("(a{1000}){1000}"), (10, ErrorKind::RepetitionTooLarge));
+    assert_eq!(error("((a{100}){100}){100}"),
+               (16, ErrorKind::RepetitionTooLarge));
+    assert_eq!(error("a{1000}{11}"), (8, ErrorKind::RepetitionTooLarge));
+    assert!(parse("(a{1000}){10}").is_ok());
+}
+
+#[test]
+fn definitions() {
+    let mut definitions = BTreeMap::new();
+
+    definitions.insert("DIGIT".to_owned(), parse("[0-9]").unwrap());
+    definitions.insert("ALPHA".to_owned(), parse("[a-z]|[A-Z]").unwrap());
+
+    let matches = |pattern: &str, s: &str| {
+        let mut nfa = parse_with_definitions(pattern, &definitions).unwrap();
+        nfa.concat(Nfa::new_accepting("match".into()));
+
+        dfa_matches(&nfa, s)
+    };
+
+    assert!(matches("{ALPHA}({ALPHA}|{DIGIT})*", "a1b2"));
+    // The definition is self-contained, `|` inside it doesn't leak
+    assert!(matches("x{ALPHA}y", "xBy"));
+    assert!(!matches("x{ALPHA}y", "xB"));
+    assert!(matches("{DIGIT}{2}", "42"));
+    assert!(matches("(?i:{ALPHA}x)", "aX"));
+
+    let error = |pattern| {
+        let e = parse_with_definitions(pattern, &definitions).err().unwrap();
+        (e.column(), e.kind())
+    };
+
+    assert_eq!(error("a{NOPE}"), (2, ErrorKind::UndefinedName));
+    assert_eq!(error("a{DIGIT"), (2, ErrorKind::UnclosedReference));
+
+    // Without definitions braces are regular characters
+    assert!(parse("{DIGIT}").is_ok());
+}
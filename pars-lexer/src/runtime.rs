@@ -0,0 +1,507 @@
+//! Run a `Dfa` directly, without generating any code.
+//!
+//! This is mostly useful to prototype and test rule sets or to build
+//! lexers whose rules are only known at runtime. The matching follows
+//! the generated lexers: the longest match wins, backtracking to the
+//! last accepting state if needed, and when nothing matches the input
+//! is skipped up to the next character that can start a match.
+//!
+//! The DFA must work on characters (not on UTF-8 bytes) and the
+//! identifier of each rule is the string of its accepting state:
+//!
+//! ```rust
+//! use pars_lexer::dfa::Dfa;
+//! use pars_lexer::nfa::Nfa;
+//! use pars_lexer::regex;
+//!
+//! let mut nfa = regex::parse("[a-z]+").unwrap();
+//! nfa.concat(Nfa::new_accepting("word".into()));
+//!
+//! let mut space = regex::parse(" +").unwrap();
+//! space.concat(Nfa::new_accepting("space".into()));
+//! nfa.combine(space);
+//!
+//! let dfa = Dfa::from_nfa(&nfa);
+//!
+//! let rules: Vec<_> = dfa.matcher().tokens("hello world".as_bytes())
+//!     .map(|t| t.unwrap().rule())
+//!     .collect();
+//!
+//! assert_eq!(rules, ["word", "space", "word"]);
+//! ```
+
+use std::error;
+use std::fmt;
+use std::iter::FusedIterator;
+use std::io::{self, Read};
+use std::str;
+
+use dfa::Dfa;
+
+/// Matcher running an in-memory `Dfa`
+#[derive(Clone, Copy)]
+pub struct Matcher<'d> {
+    dfa: &'d Dfa,
+    /// Start state of the current mode
+    start: usize,
+}
+
+impl<'d> Matcher<'d> {
+    /// Create a matcher for `dfa`, starting in its initial mode
+    pub fn new(dfa: &'d Dfa) -> Matcher<'d> {
+        Matcher {
+            dfa: dfa,
+            start: dfa.modes()[0].1,
+        }
+    }
+
+    /// Switch to mode `name`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the DFA has no such mode.
+    pub fn set_mode(&mut self, name: &str) {
+        self.start =
+            match self.dfa.modes().iter().find(|m| m.0 == name) {
+                Some(&(_, start)) => start,
+                None => panic!("Unknown mode `{}`", name),
+            };
+    }
+
+    /// Returns the identifier of the rule with the longest match at
+    /// the beginning of `input` and the length of the match in
+    /// bytes, or `None` if no rule matches.
+    pub fn longest_match(&self, input: &str) -> Option<(&'d str, usize)> {
+        let states = self.dfa.states();
+
+        let mut state = self.start;
+        let mut longest = None;
+
+        for (i, c) in input.char_indices() {
+            state =
+                match states[state].target(c as u32) {
+                    Some(s) => s,
+                    None => break,
+                };
+
+            if let Some(rule) = states[state].accepting() {
+                longest = Some((&rule[..], i + c.len_utf8()));
+            }
+        }
+
+        longest
+    }
+
+    /// Returns an iterator over the tokens of `input`. Use
+    /// `input.as_bytes()` to tokenize a `&str`.
+    pub fn tokens<R: Read>(&self, input: R) -> Tokens<'d, R> {
+        Tokens {
+            matcher: *self,
+            input: input,
+            buffer: Vec::new(),
+            buffer_offset: 0,
+            buffer_start: 0,
+            eof: false,
+            done: false,
+        }
+    }
+
+    /// Returns `true` if a match can start with `c` in the current
+    /// mode
+    fn can_start(&self, c: char) -> bool {
+        self.dfa.states()[self.start].target(c as u32).is_some()
+    }
+}
+
+/// Iterator over the tokens read from `R`. The iteration stops after
+/// the end of the input or an I/O error.
+pub struct Tokens<'d, R> {
+    matcher: Matcher<'d>,
+    input: R,
+    /// Input read so far, minus what was already dropped by
+    /// `consume`
+    buffer: Vec<u8>,
+    /// Offset in `buffer` of the first byte not consumed yet
+    buffer_offset: usize,
+    /// Offset of the first byte of `buffer` in the input
+    buffer_start: usize,
+    /// `true` once `input` reached its end
+    eof: bool,
+    /// `true` once the iteration is over
+    done: bool,
+}
+
+impl<'d, R: Read> Tokens<'d, R> {
+    /// Switch to mode `name` for the following tokens.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the DFA has no such mode.
+    pub fn set_mode(&mut self, name: &str) {
+        self.matcher.set_mode(name)
+    }
+
+    /// Make sure the buffer holds at least `len` bytes past
+    /// `buffer_offset` unless the end of the input is reached
+    fn fill(&mut self, len: usize) -> io::Result<()> {
+        let mut chunk = [0; 4096];
+
+        let len = self.buffer_offset + len;
+
+        while self.buffer.len() < len && !self.eof {
+            match self.input.read(&mut chunk) {
+                Ok(0) => self.eof = true,
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Decode the character at `pos` past `buffer_offset`. Returns its
+    /// length in bytes or `Err(len)` with the length of the invalid
+    /// sequence. `Ok(None)` is returned at the end of the input.
+    fn char_at(&mut self, pos: usize)
+               -> io::Result<Option<Result<(char, usize), usize>>> {
+        try!(self.fill(pos + 1));
+
+        let start = self.buffer_offset + pos;
+
+        if start >= self.buffer.len() {
+            return Ok(None);
+        }
+
+        let len =
+            match self.buffer[start] {
+                0x00...0x7f => 1,
+                0xc0...0xdf => 2,
+                0xe0...0xef => 3,
+                0xf0...0xf7 => 4,
+                _ => return Ok(Some(Err(1))),
+            };
+
+        try!(self.fill(pos + len));
+
+        let end = (start + len).min(self.buffer.len());
+
+        let decoded =
+            match str::from_utf8(&self.buffer[start..end]) {
+                Ok(s) => Ok((s.chars().next().unwrap(), len)),
+                Err(e) => Err(e.error_len().unwrap_or(end - start)),
+            };
+
+        Ok(Some(decoded))
+    }
+
+    /// Skip the input that doesn't match any rule starting at
+    /// `buffer_offset`, up to the next character that can start a
+    /// match. Returns the number of bytes skipped.
+    fn skip_invalid(&mut self) -> io::Result<usize> {
+        let mut pos = 0;
+
+        loop {
+            match try!(self.char_at(pos)) {
+                Some(Ok((c, len))) => {
+                    if pos > 0 && self.matcher.can_start(c) {
+                        return Ok(pos);
+                    }
+
+                    pos += len;
+                }
+                // Invalid sequences are reported separately
+                Some(Err(_)) if pos > 0 => return Ok(pos),
+                Some(Err(len)) => pos += len,
+                None => return Ok(pos),
+            }
+        }
+    }
+
+    /// Consume the next `len` bytes of the buffer. Returns their
+    /// position in the input.
+    fn consume(&mut self, len: usize) -> (usize, usize) {
+        let start = self.buffer_start + self.buffer_offset;
+
+        self.buffer_offset += len;
+
+        // Moving the rest of the buffer costs at most as much as what
+        // was consumed since the last time, which keeps the cost of
+        // each byte constant on average
+        if self.buffer_offset * 2 >= self.buffer.len() {
+            self.buffer.drain(..self.buffer_offset);
+
+            self.buffer_start += self.buffer_offset;
+            self.buffer_offset = 0;
+        }
+
+        (start, start + len)
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token<'d>>, Error> {
+        let states = self.matcher.dfa.states();
+
+        let mut state = self.matcher.start;
+        let mut pos = 0;
+        let mut accepting: Option<(usize, &'d str)> = None;
+
+        loop {
+            let (c, len) =
+                match try!(self.char_at(pos)) {
+                    Some(Ok(c)) => c,
+                    Some(Err(len)) => {
+                        if pos == 0 {
+                            let (start, end) = self.consume(len);
+
+                            return Err(Error::InvalidUtf8 {
+                                start: start,
+                                end: end,
+                            });
+                        }
+                        // Match what we have so far, the error will
+                        // be reported by the next call
+                        break;
+                    }
+                    None => {
+                        if pos == 0 {
+                            return Ok(None);
+                        }
+                        break;
+                    }
+                };
+
+            state =
+                match states[state].target(c as u32) {
+                    Some(s) => s,
+                    None => break,
+                };
+
+            pos += len;
+
+            if let Some(rule) = states[state].accepting() {
+                accepting = Some((pos, &rule[..]));
+            }
+        }
+
+        match accepting {
+            Some((len, rule)) => {
+                // Backtrack to the end of the match
+                let (start, end) = self.consume(len);
+
+                Ok(Some(Token {
+                    rule: rule,
+                    start: start,
+                    end: end,
+                }))
+            }
+            None => {
+                let len = try!(self.skip_invalid());
+
+                let (start, end) = self.consume(len);
+
+                Err(Error::NoMatch {
+                    start: start,
+                    end: end,
+                })
+            }
+        }
+    }
+}
+
+impl<'d, R: Read> Iterator for Tokens<'d, R> {
+    type Item = Result<Token<'d>, Error>;
+
+    fn next(&mut self) -> Option<Result<Token<'d>, Error>> {
+        if self.done {
+            return None;
+        }
+
+        match self.next_token() {
+            Ok(Some(t)) => Some(Ok(t)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                if let Error::Io(_) = e {
+                    self.done = true;
+                }
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl<'d, R: Read> FusedIterator for Tokens<'d, R> {}
+
+/// A token matched by a rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token<'d> {
+    rule: &'d str,
+    start: usize,
+    end: usize,
+}
+
+impl<'d> Token<'d> {
+    /// Identifier of the rule that matched, the string of its
+    /// accepting state
+    pub fn rule(&self) -> &'d str {
+        self.rule
+    }
+
+    /// Offset in bytes of the first byte of the token in the input
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// Offset in bytes past the last byte of the token in the input
+    pub fn end(&self) -> usize {
+        self.end
+    }
+}
+
+/// Tokenization error. The offending input is skipped, the iteration
+/// can go on after anything but `Error::Io`.
+#[derive(Debug)]
+pub enum Error {
+    /// The input between `start` and `end` doesn't match any rule
+    NoMatch { start: usize, end: usize },
+    /// The input between `start` and `end` is not valid UTF-8
+    InvalidUtf8 { start: usize, end: usize },
+    /// The input couldn't be read
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::NoMatch { start, end } =>
+                write!(f, "No rule matches the input at {}..{}", start, end),
+            Error::InvalidUtf8 { start, end } =>
+                write!(f, "Invalid UTF-8 at {}..{}", start, end),
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl ::std::convert::From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+#[test]
+fn tokens() {
+    use nfa::Nfa;
+    use regex;
+
+    let rules = [
+        ("if", "If"),
+        ("[a-z]+", "Id"),
+        ("[0-9]+(\\.[0-9]+)?", "Number"),
+        ("[ \n]+", "Space"),
+        ("\"", "Quote"),
+    ];
+
+    let mut nfa: Option<Nfa> = None;
+
+    for &(pattern, rule) in rules.iter() {
+        let mut r = regex::parse(pattern).unwrap();
+        r.concat(Nfa::new_accepting(rule.into()));
+
+        nfa = match nfa {
+            Some(mut n) => {
+                n.combine(r);
+                Some(n)
+            }
+            None => Some(r),
+        };
+    }
+
+    let mut modes = vec![("Initial".to_owned(), nfa.unwrap())];
+
+    let mut string = regex::parse("[^\"]+").unwrap();
+    string.concat(Nfa::new_accepting("String".into()));
+    let mut quote = regex::parse("\"").unwrap();
+    quote.concat(Nfa::new_accepting("Quote".into()));
+    string.combine(quote);
+
+    modes.push(("String".to_owned(), string));
+
+    let dfa = Dfa::from_modes(&modes);
+    let matcher = dfa.matcher();
+
+    let tokens = |input: &[u8]| -> Vec<Result<(&str, usize, usize), String>> {
+        matcher.tokens(input)
+            .map(|t| match t {
+                Ok(t) => Ok((t.rule(), t.start(), t.end())),
+                Err(e) => Err(e.to_string()),
+            })
+            .collect()
+    };
+
+    assert_eq!(matcher.longest_match("iffy"), Some(("Id", 4)));
+    assert_eq!(matcher.longest_match("if"), Some(("If", 2)));
+    assert_eq!(matcher.longest_match("3.x"), Some(("Number", 1)));
+    assert_eq!(matcher.longest_match("#"), None);
+
+    // "3." backtracks to "3", "é?!" doesn't match anything
+    assert_eq!(tokens(b"if x\n3.x \xc3\xa9?! 42"),
+               vec![Ok(("If", 0, 2)),
+                    Ok(("Space", 2, 3)),
+                    Ok(("Id", 3, 4)),
+                    Ok(("Space", 4, 5)),
+                    Ok(("Number", 5, 6)),
+                    Err("No rule matches the input at 6..7".into()),
+                    Ok(("Id", 7, 8)),
+                    Ok(("Space", 8, 9)),
+                    Err("No rule matches the input at 9..13".into()),
+                    Ok(("Space", 13, 14)),
+                    Ok(("Number", 14, 16))]);
+
+    // Invalid UTF-8 ends the current match and is reported on its own
+    assert_eq!(tokens(b"ab\xffc\xe2\x82"),
+               vec![Ok(("Id", 0, 2)),
+                    Err("Invalid UTF-8 at 2..3".into()),
+                    Ok(("Id", 3, 4)),
+                    Err("Invalid UTF-8 at 4..6".into())]);
+
+    // Offsets stay right across several reads and compactions
+    let input = "abc ".repeat(3000);
+
+    let all = tokens(input.as_bytes());
+
+    assert_eq!(all.len(), 6000);
+    assert_eq!(all[4097], Ok(("Space", 8195, 8196)));
+    assert_eq!(all[5999], Ok(("Space", 11999, 12000)));
+
+    // Modes are switched between tokens
+    let mut tokens = matcher.tokens("a\"b c\"d".as_bytes());
+    let mut rules = Vec::new();
+
+    while let Some(t) = tokens.next() {
+        let t = t.unwrap();
+
+        match (t.rule(), rules.last() == Some(&"String")) {
+            ("Quote", false) => tokens.set_mode("String"),
+            ("Quote", true) => tokens.set_mode("Initial"),
+            _ => (),
+        }
+
+        rules.push(t.rule());
+    }
+
+    assert_eq!(rules, ["Id", "Quote", "String", "Quote", "Id"]);
+
+    // The iteration stays over
+    assert!(tokens.next().is_none());
+}
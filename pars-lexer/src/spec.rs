@@ -0,0 +1,1046 @@
+//! Declarative lexer specification files.
+//!
+//! A specification (conventionally stored in a `.parsl` file) is
+//! split in two sections separated by a line containing `%%`, much
+//! like flex's `.l` files:
+//!
+//! ```text
+//! # Comments start with '#' and run until the end of the line.
+//!
+//! # Directives
+//! %token Token
+//! %option case_insensitive
+//! %mode Comment
+//!
+//! # Definitions: a name followed by a pattern. Definitions can be
+//! # referenced in subsequent patterns using `{NAME}`.
+//! DIGIT   [0-9]
+//! ID      [a-zA-Z_][a-zA-Z_0-9]*
+//!
+//! %%
+//!
+//! # Rules: a pattern followed by `=>` and a block of Rust code. When
+//! # several rules match the same input the first one wins.
+//! {DIGIT}+    => { Some(Token::Number(_lexer_match.as_str().into_owned())) }
+//! {ID}        => { Some(Token::Id(_lexer_match.as_str().into_owned())) }
+//! [ \t\n]+    => { None }
+//!
+//! # Rules can be restricted to a list of modes, `<*>` means all the
+//! # modes. Rules without a mode list belong to the `Initial` mode.
+//! <Initial,Comment>/\*  => { self.push_mode(Mode::Comment); None }
+//! <Comment>\*/          => { self.pop_mode(); None }
+//! <Comment>[^*/]+|[*/]  => { None }
+//! ```
+//!
+//! A definition's pattern runs until the end of its line.
+//!
+//! Patterns use the syntax described in the `regex` module. In the
+//! rules section a pattern ends at the first whitespace that isn't
+//! escaped or within a character class, so `[ ]` or `\ ` must be used
+//! to match a space.
+//!
+//! The supported directives are:
+//!
+//! * `%token <type>`: the type returned by the actions, defaults to
+//!   `Token`.
+//! * `%option case_insensitive`: all the rules are case-insensitive,
+//!   as if they were all wrapped in `(?i:...)`.
+//! * `%mode <name>...`: declare one or more lexer modes (also known
+//!   as start conditions) besides the implicit `Initial` mode. Each
+//!   mode becomes a variant of the generated `Mode` enum.
+//! * `%error { action }`: action run on input that doesn't match any
+//!   rule instead of returning a `NoMatch` error, see
+//!   `CodeGen::set_error_action`.
+//!
+//! A rule starting with `<` followed by `*` or an identifier is
+//! always parsed as having a mode list, use `\<` to match a `<`
+//! followed by a letter. Actions can switch modes using the
+//! `push_mode`, `pop_mode` and `set_mode` methods of the lexer
+//! (`self`), after the last use of `_lexer_match`.
+
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use dfa::INITIAL_MODE;
+use nfa::Nfa;
+use regex;
+
+/// A parsed lexer specification
+pub struct Spec {
+    /// Type returned by the rule actions
+    token_type: String,
+    /// `true` if all the rules are case-insensitive
+    case_insensitive: bool,
+    /// Action set by the `%error` directive
+    error_action: Option<String>,
+    /// Rules in priority order
+    rules: Vec<Rule>,
+    /// Name of each mode and combination of the NFAs of its rules.
+    /// The first mode is `Initial`.
+    modes: Vec<(String, Nfa)>,
+}
+
+impl Spec {
+    /// Load and parse the specification in the file at `path`
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Spec, Error> {
+        let path = path.as_ref();
+
+        let mut source = String::new();
+
+        let read =
+            File::open(path).and_then(|mut f| f.read_to_string(&mut source));
+
+        if let Err(e) = read {
+            return Err(Error {
+                file: path.to_owned(),
+                line: 0,
+                column: 0,
+                kind: ErrorKind::Io(e),
+            });
+        }
+
+        Spec::parse(path, &source)
+    }
+
+    /// Parse the specification in `source`. `file` is only used to
+    /// report errors.
+    pub fn parse<P: AsRef<Path>>(file: P, source: &str) -> Result<Spec, Error> {
+        let mut parser = Parser::new(file.as_ref(), source);
+
+        parser.parse()
+    }
+
+    /// Type returned by the rule actions
+    pub fn token_type(&self) -> &str {
+        &self.token_type
+    }
+
+    /// Returns `true` if the `case_insensitive` option is set
+    pub fn case_insensitive(&self) -> bool {
+        self.case_insensitive
+    }
+
+    /// Action run on invalid input, set by the `%error` directive
+    pub fn error_action(&self) -> Option<&str> {
+        self.error_action.as_deref()
+    }
+
+    /// The rules, in priority order
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// The combined NFA of the rules of the `Initial` mode
+    pub fn nfa(&self) -> &Nfa {
+        &self.modes[0].1
+    }
+
+    /// The name and combined NFA of every mode, ready to be converted
+    /// into a `Dfa` using `Dfa::from_modes`. The first mode is
+    /// `Initial`.
+    pub fn modes(&self) -> &[(String, Nfa)] {
+        &self.modes
+    }
+}
+
+/// A single `pattern => { action }` rule
+pub struct Rule {
+    pattern: String,
+    action: String,
+    line: usize,
+    modes: Vec<String>,
+}
+
+impl Rule {
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Action code, including the surrounding braces
+    pub fn action(&self) -> &str {
+        &self.action
+    }
+
+    /// Line of the rule in the specification file, starting at 1
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Modes this rule is active in
+    pub fn modes(&self) -> &[String] {
+        &self.modes
+    }
+}
+
+struct Parser<'a> {
+    file: &'a Path,
+    chars: Vec<char>,
+    /// Index of the next character to be parsed in `chars`
+    pos: usize,
+    token_type: String,
+    case_insensitive: bool,
+    error_action: Option<String>,
+    definitions: BTreeMap<String, Nfa>,
+    /// Declared modes and the position of their declaration
+    modes: Vec<(String, usize)>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(file: &'a Path, source: &str) -> Parser<'a> {
+        Parser {
+            file: file,
+            chars: source.chars().collect(),
+            pos: 0,
+            token_type: "Token".into(),
+            case_insensitive: false,
+            error_action: None,
+            definitions: BTreeMap::new(),
+            modes: vec![(INITIAL_MODE.into(), 0)],
+        }
+    }
+
+    fn parse(&mut self) -> Result<Spec, Error> {
+        try!(self.parse_header());
+
+        let mut rules: Vec<Rule> = Vec::new();
+        let mut nfas: Vec<Option<Nfa>> = vec![None; self.modes.len()];
+
+        loop {
+            self.skip_whitespace_and_comments();
+
+            if self.peek().is_none() {
+                break;
+            }
+
+            let (rule, rule_nfa) = try!(self.parse_rule());
+
+            for ((name, _), nfa) in self.modes.iter().zip(nfas.iter_mut()) {
+                if !rule.modes.contains(name) {
+                    continue;
+                }
+
+                *nfa = match nfa.take() {
+                    Some(mut n) => {
+                        n.combine(rule_nfa.clone());
+                        Some(n)
+                    }
+                    None => Some(rule_nfa.clone()),
+                };
+            }
+
+            rules.push(rule);
+        }
+
+        let mut modes = Vec::with_capacity(nfas.len());
+
+        for (&(ref name, pos), nfa) in self.modes.iter().zip(nfas) {
+            match nfa {
+                Some(n) => modes.push((name.clone(), n)),
+                None if name == INITIAL_MODE =>
+                    return Err(self.error(ErrorKind::NoRules)),
+                None => return Err(self.error_at(pos, ErrorKind::EmptyMode)),
+            }
+        }
+
+        Ok(Spec {
+            token_type: self.token_type.clone(),
+            case_insensitive: self.case_insensitive,
+            error_action: self.error_action.clone(),
+            rules: rules,
+            modes: modes,
+        })
+    }
+
+    /// Parse the directives and definitions up to the `%%` separator
+    fn parse_header(&mut self) -> Result<(), Error> {
+        loop {
+            self.skip_whitespace_and_comments();
+
+            match self.peek() {
+                None => return Err(self.error(ErrorKind::MissingSeparator)),
+                Some('%') => {
+                    if self.chars.get(self.pos + 1) == Some(&'%') {
+                        self.pos += 2;
+                        return self.expect_end_of_line();
+                    }
+
+                    try!(self.parse_directive());
+                }
+                Some(_) => try!(self.parse_definition()),
+            }
+        }
+    }
+
+    /// Parse a `%directive` line. The cursor is on the `%`.
+    fn parse_directive(&mut self) -> Result<(), Error> {
+        let start = self.pos;
+
+        // Skip the '%'
+        self.next();
+
+        let directive = self.parse_identifier();
+
+        self.skip_blanks();
+
+        match &*directive {
+            "token" => {
+                let value_start = self.pos;
+                let mut token_type = String::new();
+
+                while !self.at_end_of_line() && self.peek() != Some('#') {
+                    token_type.push(self.next().unwrap());
+                }
+
+                let token_type = token_type.trim_end().to_owned();
+
+                if token_type.is_empty() {
+                    return Err(self.error_at(value_start,
+                                             ErrorKind::MissingValue));
+                }
+
+                self.token_type = token_type;
+            }
+            "option" => {
+                loop {
+                    let option_start = self.pos;
+                    let option = self.parse_identifier();
+
+                    match &*option {
+                        "case_insensitive" => self.case_insensitive = true,
+                        "" => return Err(self.error_at(option_start,
+                                                       ErrorKind::MissingValue)),
+                        _ => return Err(self.error_at(option_start,
+                                                      ErrorKind::UnknownOption)),
+                    }
+
+                    self.skip_blanks();
+
+                    if self.at_end_of_line() {
+                        break;
+                    }
+                }
+            }
+            "mode" => {
+                loop {
+                    let mode_start = self.pos;
+                    let mode = self.parse_identifier();
+
+                    if mode.is_empty() {
+                        return Err(self.error_at(mode_start,
+                                                 ErrorKind::MissingValue));
+                    }
+
+                    if self.modes.iter().any(|(m, _)| *m == mode) {
+                        return Err(self.error_at(mode_start,
+                                                 ErrorKind::DuplicateMode));
+                    }
+
+                    self.modes.push((mode, mode_start));
+
+                    self.skip_blanks();
+
+                    if self.at_end_of_line() || self.peek() == Some('#') {
+                        break;
+                    }
+                }
+            }
+            "error" => {
+                if self.error_action.is_some() {
+                    return Err(self.error_at(start,
+                                             ErrorKind::DuplicateDirective));
+                }
+
+                self.error_action = Some(try!(self.scan_action()));
+            }
+            _ => return Err(self.error_at(start, ErrorKind::UnknownDirective)),
+        }
+
+        self.expect_end_of_line()
+    }
+
+    /// Parse a `NAME pattern` definition line
+    fn parse_definition(&mut self) -> Result<(), Error> {
+        let start = self.pos;
+
+        let name = self.parse_identifier();
+
+        if name.is_empty() {
+            return Err(self.error(ErrorKind::InvalidName));
+        }
+
+        if self.definitions.contains_key(&name) {
+            return Err(self.error_at(start, ErrorKind::DuplicateDefinition));
+        }
+
+        if !self.skip_blanks() {
+            return Err(self.error(ErrorKind::InvalidName));
+        }
+
+        let pattern_start = self.pos;
+        let pattern = self.rest_of_line();
+
+        if pattern.is_empty() {
+            return Err(self.error_at(pattern_start, ErrorKind::MissingValue));
+        }
+
+        let nfa = try!(self.parse_pattern(&pattern, pattern_start));
+
+        self.definitions.insert(name, nfa);
+
+        self.expect_end_of_line()
+    }
+
+    /// Parse a `<modes> pattern => { action }` rule
+    fn parse_rule(&mut self) -> Result<(Rule, Nfa), Error> {
+        let line = self.position(self.pos).0;
+
+        let modes = try!(self.parse_mode_list());
+
+        let pattern_start = self.pos;
+
+        let pattern = self.scan_pattern();
+
+        let mut nfa = try!(self.parse_pattern(&pattern, pattern_start));
+
+        if self.case_insensitive {
+            nfa.fold_case();
+        }
+
+        self.skip_whitespace_and_comments();
+
+        if self.peek() != Some('=') || self.chars.get(self.pos + 1) != Some(&'>') {
+            return Err(self.error(ErrorKind::ExpectedArrow));
+        }
+
+        self.pos += 2;
+
+        self.skip_whitespace_and_comments();
+
+        let action = try!(self.scan_action());
+
+        nfa.concat(Nfa::new_accepting(action.clone()));
+
+        let rule = Rule {
+            pattern: pattern,
+            action: action,
+            line: line,
+            modes: modes,
+        };
+
+        Ok((rule, nfa))
+    }
+
+    /// Parse the optional `<Mode1,Mode2>` or `<*>` list at the start
+    /// of a rule. Returns the modes the rule belongs to.
+    fn parse_mode_list(&mut self) -> Result<Vec<String>, Error> {
+        let is_list =
+            self.peek() == Some('<') &&
+            match self.chars.get(self.pos + 1) {
+                Some(&c) => c == '*' || c == '_' || c.is_ascii_alphabetic(),
+                None => false,
+            };
+
+        if !is_list {
+            return Ok(vec![INITIAL_MODE.into()]);
+        }
+
+        let start = self.pos;
+
+        // Skip the '<'
+        self.next();
+
+        let mut modes = Vec::new();
+
+        if self.peek() == Some('*') {
+            self.next();
+
+            modes.extend(self.modes.iter().map(|(m, _)| m.clone()));
+        } else {
+            loop {
+                self.skip_blanks();
+
+                let mode_start = self.pos;
+                let mode = self.parse_identifier();
+
+                if mode.is_empty() {
+                    return Err(self.error_at(start,
+                                             ErrorKind::UnclosedModeList));
+                }
+
+                if !self.modes.iter().any(|(m, _)| *m == mode) {
+                    return Err(self.error_at(mode_start,
+                                             ErrorKind::UnknownMode));
+                }
+
+                modes.push(mode);
+
+                self.skip_blanks();
+
+                if self.peek() != Some(',') {
+                    break;
+                }
+
+                self.next();
+            }
+        }
+
+        if self.next() != Some('>') {
+            return Err(self.error_at(start, ErrorKind::UnclosedModeList));
+        }
+
+        self.skip_blanks();
+
+        Ok(modes)
+    }
+
+    /// Parse `pattern` which starts at `start` in the source,
+    /// translating the regex error position if needed.
+    fn parse_pattern(&self, pattern: &str, start: usize) -> Result<Nfa, Error> {
+        regex::parse_with_definitions(pattern, &self.definitions)
+            .map_err(|e| {
+                self.error_at(start + e.column() - 1,
+                              ErrorKind::Regex(e.kind()))
+            })
+    }
+
+    /// Return the pattern at the current position. The pattern ends
+    /// at the first whitespace that's not escaped or within a
+    /// character class.
+    fn scan_pattern(&mut self) -> String {
+        let mut pattern = String::new();
+        let mut in_class = false;
+
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() && !in_class {
+                break;
+            }
+
+            self.next();
+            pattern.push(c);
+
+            match c {
+                '\\' => {
+                    if let Some(e) = self.next() {
+                        pattern.push(e);
+                    }
+                }
+                '[' => in_class = true,
+                ']' => in_class = false,
+                _ => (),
+            }
+        }
+
+        pattern
+    }
+
+    /// Return the block of code at the current position, including
+    /// the surrounding braces. String and character literals as well
+    /// as comments are skipped when looking for the closing brace.
+    fn scan_action(&mut self) -> Result<String, Error> {
+        let start = self.pos;
+
+        if self.peek() != Some('{') {
+            return Err(self.error(ErrorKind::ExpectedAction));
+        }
+
+        let mut depth = 0;
+
+        while let Some(c) = self.next() {
+            match c {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+
+                    if depth == 0 {
+                        return Ok(self.chars[start..self.pos].iter().cloned()
+                                  .collect());
+                    }
+                }
+                '"' => self.skip_string(0),
+                'r' if self.is_raw_string_start() => {
+                    let mut hashes = 0;
+
+                    while self.next() == Some('#') {
+                        hashes += 1;
+                    }
+
+                    self.skip_string(hashes);
+                }
+                '\'' => self.skip_char_literal(),
+                '/' if self.peek() == Some('/') => {
+                    while !self.at_end_of_line() {
+                        self.next();
+                    }
+                }
+                '/' if self.peek() == Some('*') => {
+                    self.next();
+
+                    while let Some(c) = self.next() {
+                        if c == '*' && self.peek() == Some('/') {
+                            self.next();
+                            break;
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Err(self.error_at(start, ErrorKind::UnclosedAction))
+    }
+
+    /// Returns `true` if the `r` we just consumed starts a raw string
+    /// literal such as `r"..."` or `r#"..."#`
+    fn is_raw_string_start(&self) -> bool {
+        // The 'r' must not be the end of an identifier (`br` is a
+        // raw byte string prefix though)
+        let prev = if self.pos >= 2 { Some(self.chars[self.pos - 2]) } else { None };
+
+        let standalone =
+            match prev {
+                Some(p) => p == 'b' || !(p.is_alphanumeric() || p == '_'),
+                None => true,
+            };
+
+        standalone && self.chars[self.pos..].iter()
+            .find(|&&c| c != '#') == Some(&'"')
+    }
+
+    /// Skip a string literal, the opening quote has already been
+    /// consumed. `hashes` is the number of `#` of a raw string
+    /// literal, in which case escapes aren't handled.
+    fn skip_string(&mut self, hashes: usize) {
+        while let Some(c) = self.next() {
+            match c {
+                '\\' if hashes == 0 => { self.next(); }
+                '"' => {
+                    let closing =
+                        self.chars[self.pos..].iter()
+                        .take(hashes)
+                        .filter(|&&c| c == '#')
+                        .count();
+
+                    if closing == hashes {
+                        self.pos += hashes;
+                        return;
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Skip a character literal, the opening quote has already been
+    /// consumed. If it turns out to be a lifetime nothing is skipped.
+    fn skip_char_literal(&mut self) {
+        match self.peek() {
+            Some('\\') => {
+                // Escape sequence, skip until the closing quote
+                self.next();
+                self.next();
+
+                while let Some(c) = self.next() {
+                    if c == '\'' {
+                        break;
+                    }
+                }
+            }
+            Some(_) if self.chars.get(self.pos + 1) == Some(&'\'') => {
+                self.pos += 2;
+            }
+            // Lifetime
+            _ => (),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+
+        if c.is_some() {
+            self.pos += 1;
+        }
+
+        c
+    }
+
+    /// Parse an identifier `[a-zA-Z_][a-zA-Z_0-9]*`, returns an empty
+    /// string if there's no identifier at the current position.
+    fn parse_identifier(&mut self) -> String {
+        let mut ident = String::new();
+
+        while let Some(c) = self.peek() {
+            let valid =
+                c == '_' || c.is_ascii_alphabetic() ||
+                (!ident.is_empty() && c.is_ascii_digit());
+
+            if !valid {
+                break;
+            }
+
+            ident.push(c);
+            self.next();
+        }
+
+        ident
+    }
+
+    /// Skip spaces and tabs, returns `true` if anything was skipped
+    fn skip_blanks(&mut self) -> bool {
+        let start = self.pos;
+
+        while self.peek() == Some(' ') || self.peek() == Some('\t') {
+            self.next();
+        }
+
+        self.pos != start
+    }
+
+    fn skip_whitespace_and_comments(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == '#' {
+                while !self.at_end_of_line() {
+                    self.next();
+                }
+            } else if c.is_whitespace() {
+                self.next();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn at_end_of_line(&self) -> bool {
+        match self.peek() {
+            None | Some('\n') => true,
+            Some('\r') => self.chars.get(self.pos + 1) == Some(&'\n'),
+            _ => false,
+        }
+    }
+
+    /// Return the rest of the current line with the trailing
+    /// whitespace removed. The cursor is left at the end of the line.
+    fn rest_of_line(&mut self) -> String {
+        let mut s = String::new();
+
+        while !self.at_end_of_line() {
+            s.push(self.next().unwrap());
+        }
+
+        s.trim_end().to_owned()
+    }
+
+    /// Make sure nothing but blanks and comments remain on the line
+    fn expect_end_of_line(&mut self) -> Result<(), Error> {
+        self.skip_blanks();
+
+        if self.peek() == Some('#') {
+            while !self.at_end_of_line() {
+                self.next();
+            }
+        }
+
+        if self.at_end_of_line() {
+            Ok(())
+        } else {
+            Err(self.error(ErrorKind::TrailingCharacters))
+        }
+    }
+
+    /// Return the line and column of the character at `pos`, both
+    /// starting at 1
+    fn position(&self, pos: usize) -> (usize, usize) {
+        let mut line = 1;
+        let mut column = 1;
+
+        for &c in &self.chars[..pos] {
+            if c == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
+
+        (line, column)
+    }
+
+    fn error(&self, kind: ErrorKind) -> Error {
+        self.error_at(self.pos, kind)
+    }
+
+    fn error_at(&self, pos: usize, kind: ErrorKind) -> Error {
+        let (line, column) = self.position(pos);
+
+        Error {
+            file: self.file.to_owned(),
+            line: line,
+            column: column,
+            kind: kind,
+        }
+    }
+}
+
+/// Error returned when a specification fails to load
+#[derive(Debug)]
+pub struct Error {
+    file: PathBuf,
+    line: usize,
+    column: usize,
+    kind: ErrorKind,
+}
+
+impl Error {
+    /// Path of the specification file
+    pub fn file(&self) -> &Path {
+        &self.file
+    }
+
+    /// Line of the error, starting at 1. 0 for I/O errors.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Column of the error, starting at 1. 0 for I/O errors.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ErrorKind::Io(_) =>
+                write!(f, "{}: {}", self.file.display(), self.kind),
+            _ => write!(f, "{}:{}:{}: {}",
+                        self.file.display(), self.line, self.column, self.kind),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self.kind {
+            ErrorKind::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// The various specification errors
+#[derive(Debug)]
+pub enum ErrorKind {
+    /// The file couldn't be read
+    Io(io::Error),
+    /// Invalid pattern
+    Regex(regex::ErrorKind),
+    /// The `%%` separating the definitions from the rules is missing
+    MissingSeparator,
+    /// Unknown `%directive`
+    UnknownDirective,
+    /// Unknown `%option`
+    UnknownOption,
+    /// Directive or definition without a value
+    MissingValue,
+    /// Definition name isn't a valid identifier
+    InvalidName,
+    /// The same name is defined twice
+    DuplicateDefinition,
+    /// A directive that can only appear once is repeated
+    DuplicateDirective,
+    /// Unexpected characters at the end of a line
+    TrailingCharacters,
+    /// Rule pattern not followed by `=>`
+    ExpectedArrow,
+    /// `=>` not followed by a `{` block
+    ExpectedAction,
+    /// Action block without a closing `}`
+    UnclosedAction,
+    /// The specification has no rules
+    NoRules,
+    /// The same mode is declared twice
+    DuplicateMode,
+    /// Rule for a mode that hasn't been declared
+    UnknownMode,
+    /// Invalid `<Mode1,Mode2>` list
+    UnclosedModeList,
+    /// A declared mode has no rules
+    EmptyMode,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ErrorKind::Io(ref e) => write!(f, "{}", e),
+            ErrorKind::Regex(ref e) => write!(f, "invalid pattern: {}", e),
+            ErrorKind::MissingSeparator => write!(f, "missing `%%` separator"),
+            ErrorKind::UnknownDirective => write!(f, "unknown directive"),
+            ErrorKind::UnknownOption => write!(f, "unknown option"),
+            ErrorKind::MissingValue => write!(f, "missing value"),
+            ErrorKind::InvalidName => write!(f, "invalid definition name"),
+            ErrorKind::DuplicateDefinition =>
+                write!(f, "duplicate definition"),
+            ErrorKind::DuplicateDirective =>
+                write!(f, "duplicate directive"),
+            ErrorKind::TrailingCharacters =>
+                write!(f, "unexpected characters at the end of the line"),
+            ErrorKind::ExpectedArrow => write!(f, "expected `=>`"),
+            ErrorKind::ExpectedAction => write!(f, "expected `{{` block"),
+            ErrorKind::UnclosedAction => write!(f, "unclosed action block"),
+            ErrorKind::NoRules => write!(f, "no rules"),
+            ErrorKind::DuplicateMode => write!(f, "duplicate mode"),
+            ErrorKind::UnknownMode => write!(f, "unknown mode"),
+            ErrorKind::UnclosedModeList => write!(f, "unclosed mode list"),
+            ErrorKind::EmptyMode => write!(f, "mode without rules"),
+        }
+    }
+}
+
+#[cfg(test)]
+fn parse_error(source: &str) -> (usize, usize, String) {
+    let e = Spec::parse("test.parsl", source).err().unwrap();
+
+    (e.line(), e.column(), e.kind().to_string())
+}
+
+#[test]
+fn parse() {
+    let source = "\
+# Test lexer
+%token  MyToken   # trailing comment
+%option case_insensitive
+
+DIGIT   [0-9]
+NUMBER  {DIGIT}+(\\.{DIGIT}+)?
+
+%%
+
+{NUMBER}    => { Some(MyToken::Number) }
+[a-z ]+     =>
+    {
+        // Braces in comments and literals: } /* } */
+        let _ = (\"}\", '}', r#\"}\"#, '\\'');
+        fn f<'a>(s: &'a str) -> &'a str { s }
+        Some(MyToken::Word)
+    }
+#[ \\t]+  => { None }
+\\#         => { None }
+";
+
+    let spec = Spec::parse("test.parsl", source).unwrap();
+
+    assert_eq!(spec.token_type(), "MyToken");
+    assert!(spec.case_insensitive());
+
+    let rules = spec.rules();
+
+    assert_eq!(rules.len(), 3);
+
+    assert_eq!(rules[0].pattern(), "{NUMBER}");
+    assert_eq!(rules[0].action(), "{ Some(MyToken::Number) }");
+    assert_eq!(rules[0].line(), 10);
+
+    assert_eq!(rules[1].pattern(), "[a-z ]+");
+    assert!(rules[1].action().starts_with("{\n"));
+    assert!(rules[1].action().ends_with("Some(MyToken::Word)\n    }"));
+    assert_eq!(rules[1].line(), 11);
+
+    assert_eq!(rules[2].pattern(), "\\#");
+    assert_eq!(rules[2].line(), 19);
+}
+
+#[test]
+fn modes() {
+    let source = "\
+%mode Str Comment
+%error {
+    Some(Token::Error)
+}
+
+%%
+
+<*>/\\*             => { self.push_mode(Mode::Comment); None }
+<Comment>\\*/        => { self.pop_mode(); None }
+\"                  => { self.push_mode(Mode::Str); None }
+<Str>\"             => { self.pop_mode(); None }
+<Initial, Str>[a-z]+ => { None }
+<=                  => { None }
+";
+
+    let spec = Spec::parse("test.parsl", source).unwrap();
+
+    let modes: Vec<_> = spec.modes().iter().map(|&(ref m, _)| &**m).collect();
+
+    assert_eq!(modes, ["Initial", "Str", "Comment"]);
+
+    assert_eq!(spec.error_action(), Some("{\n    Some(Token::Error)\n}"));
+
+    let rules = spec.rules();
+
+    assert_eq!(rules[0].pattern(), "/\\*");
+    assert_eq!(rules[0].modes(), ["Initial", "Str", "Comment"]);
+    assert_eq!(rules[1].modes(), ["Comment"]);
+    assert_eq!(rules[2].modes(), ["Initial"]);
+    assert_eq!(rules[4].pattern(), "[a-z]+");
+    assert_eq!(rules[4].modes(), ["Initial", "Str"]);
+    assert_eq!(rules[5].pattern(), "<=");
+    assert_eq!(rules[5].modes(), ["Initial"]);
+}
+
+#[test]
+fn errors() {
+    assert_eq!(parse_error("A [a-z]\n"),
+               (2, 1, "missing `%%` separator".into()));
+    assert_eq!(parse_error("%tokn T\n%%\n"),
+               (1, 1, "unknown directive".into()));
+    assert_eq!(parse_error("%option foo\n%%\n"),
+               (1, 9, "unknown option".into()));
+    assert_eq!(parse_error("A [a-z]\nA [0-9]\n%%\n"),
+               (2, 1, "duplicate definition".into()));
+    assert_eq!(parse_error("A [a-z\n%%\n"),
+               (1, 3, "invalid pattern: unclosed character class".into()));
+    assert_eq!(parse_error("%%\n\n  ab{B}c => { None }\n"),
+               (3, 5, "invalid pattern: undefined name".into()));
+    assert_eq!(parse_error("%%\nabc { None }\n"),
+               (2, 5, "expected `=>`".into()));
+    assert_eq!(parse_error("%%\nabc => None\n"),
+               (2, 8, "expected `{` block".into()));
+    assert_eq!(parse_error("%%\nabc => { \"}\" \n"),
+               (2, 8, "unclosed action block".into()));
+    assert_eq!(parse_error("%%\n# nothing\n"),
+               (3, 1, "no rules".into()));
+    assert_eq!(parse_error("%error { None }\n%error { None }\n%%\n"),
+               (2, 1, "duplicate directive".into()));
+    assert_eq!(parse_error("%mode A B A\n%%\n"),
+               (1, 11, "duplicate mode".into()));
+    assert_eq!(parse_error("%mode Initial\n%%\n"),
+               (1, 7, "duplicate mode".into()));
+    assert_eq!(parse_error("%mode A\n%%\n<A, B>a => { None }\n"),
+               (3, 5, "unknown mode".into()));
+    assert_eq!(parse_error("%mode A\n%%\n<A a => { None }\n"),
+               (3, 1, "unclosed mode list".into()));
+    assert_eq!(parse_error("%mode A B\n%%\n<A>a => { None }\nb => { None }\n"),
+               (1, 9, "mode without rules".into()));
+    assert_eq!(parse_error("%mode A\n%%\n<A>a => { None }\n"),
+               (4, 1, "no rules".into()));
+
+    let e = Spec::from_file("/does/not/exist.parsl").err().unwrap();
+
+    match *e.kind() {
+        ErrorKind::Io(_) => (),
+        ref k => panic!("unexpected error {:?}", k),
+    }
+
+    assert_eq!(e.to_string().find("/does/not/exist.parsl: "), Some(0));
+}
@@ -5,9 +5,11 @@ use std::env;
 use std::path::Path;
 
 use pars_lexer::nfa::Nfa;
-use pars_lexer::dfa::Dfa;
-use pars_lexer::codegen::CodeGen;
-use pars_lexer::character::Interval;
+use pars_lexer::dfa::{Dfa, INITIAL_MODE};
+use pars_lexer::codegen::{Backend, CodeGen, ColumnUnit, InputMode, InputSource};
+use pars_lexer::character::{Interval, IntervalSet};
+use pars_lexer::regex;
+use pars_lexer::build::Build;
 
 fn main() {
     simple();
@@ -15,6 +17,15 @@ fn main() {
     intersecting_intervals();
     utf8();
     c_basic();
+    regex();
+    unicode();
+    case_insensitive();
+    spec();
+    modes();
+    positions();
+    bytes();
+    slice();
+    limits();
 }
 
 pub fn simple() {
@@ -352,3 +363,361 @@ pub fn c_basic() {
 
     gen.generate(&dfa, &mut out).unwrap();
 }
+
+pub fn regex() {
+    // [0-9]+(\.[0-9]+)?
+    let mut number = regex::parse("\\d+(\\.\\d+)?").unwrap();
+    number.concat(Nfa::new_accepting(stringify!({
+        let n = _lexer_match.as_str().into_owned();
+
+        Some(Token::Number(n))
+    }).into()));
+
+    let mut id = regex::parse("[a-zA-Z_]\\w*").unwrap();
+    id.concat(Nfa::new_accepting(stringify!({
+        let id = _lexer_match.as_str().into_owned();
+
+        Some(Token::Id(id))
+    }).into()));
+
+    let mut op = regex::parse("[-+*/=]|\\*\\*").unwrap();
+    op.concat(Nfa::new_accepting(stringify!({
+        let op = _lexer_match.as_str().into_owned();
+
+        Some(Token::Op(op))
+    }).into()));
+
+    let mut string = regex::parse("\"([^\"\\\\]|\\\\.)*\"").unwrap();
+    string.concat(Nfa::new_accepting(stringify!({
+        let s = _lexer_match.as_str().into_owned();
+
+        Some(Token::Str(s))
+    }).into()));
+
+    let mut spaces = regex::parse("\\s+").unwrap();
+    spaces.concat(Nfa::new_accepting(stringify!({
+        None
+    }).into()));
+
+    let mut nfa = number;
+    nfa.combine(id);
+    nfa.combine(op);
+    nfa.combine(string);
+    nfa.combine(spaces);
+
+    let dfa = Dfa::from_nfa(&nfa);
+
+    let outfile = Path::new(&env::var("OUT_DIR").unwrap()).join("regex.rs");
+
+    let mut out = File::create(outfile).unwrap();
+
+    let mut gen = CodeGen::new();
+
+    gen.set_token_type("Token");
+
+    gen.generate(&dfa, &mut out).unwrap();
+}
+
+pub fn unicode() {
+    // \p{Greek}+
+    let mut greek = Nfa::new_set(&IntervalSet::script("Greek").unwrap());
+    greek.positive();
+    greek.concat(Nfa::new_accepting(stringify!({
+        let s = _lexer_match.as_str().into_owned();
+
+        Some(Token::Greek(s))
+    }).into()));
+
+    let mut id = regex::parse("\\p{XID_Start}\\p{XID_Continue}*").unwrap();
+    id.concat(Nfa::new_accepting(stringify!({
+        let s = _lexer_match.as_str().into_owned();
+
+        Some(Token::Id(s))
+    }).into()));
+
+    let mut number = regex::parse("\\p{Nd}+").unwrap();
+    number.concat(Nfa::new_accepting(stringify!({
+        let s = _lexer_match.as_str().into_owned();
+
+        Some(Token::Number(s))
+    }).into()));
+
+    let mut spaces = regex::parse("\\s+").unwrap();
+    spaces.concat(Nfa::new_accepting(stringify!({
+        None
+    }).into()));
+
+    let mut nfa = greek;
+    nfa.combine(id);
+    nfa.combine(number);
+    nfa.combine(spaces);
+
+    // Generate the same lexer running on UTF-8 bytes and using
+    // transition tables
+    let variants = [
+        ("unicode.rs", InputMode::Utf8, Backend::Match),
+        ("unicode-bytes.rs", InputMode::Utf8Bytes, Backend::Match),
+        ("unicode-table.rs", InputMode::Utf8, Backend::Table),
+    ];
+
+    let modes = [(INITIAL_MODE.to_owned(), nfa)];
+
+    for &(file, mode, backend) in variants.iter() {
+        let dfa =
+            match mode {
+                InputMode::Utf8Bytes => Dfa::from_modes_utf8(&modes),
+                _ => Dfa::from_modes(&modes),
+            };
+
+        let outfile = Path::new(&env::var("OUT_DIR").unwrap()).join(file);
+
+        let mut out = File::create(outfile).unwrap();
+
+        let mut gen = CodeGen::new();
+
+        gen.set_token_type("Token");
+        gen.set_input_mode(mode);
+        gen.set_backend(backend);
+
+        gen.generate(&dfa, &mut out).unwrap();
+    }
+}
+
+pub fn case_insensitive() {
+    fn keyword(k: &str) -> Nfa {
+        let mut nfa = Nfa::new_empty();
+
+        for c in k.chars() {
+            nfa.concat(Nfa::new(Interval::new_single(c)));
+        }
+
+        nfa.fold_case();
+
+        nfa.concat(Nfa::new_accepting(stringify!({
+            let k = _lexer_match.as_str().to_lowercase();
+
+            Some(Token::Keyword(k))
+        }).into()));
+
+        nfa
+    }
+
+    let mut id = regex::parse("[a-z_][a-z_0-9]*").unwrap();
+    id.concat(Nfa::new_accepting(stringify!({
+        let id = _lexer_match.as_str().into_owned();
+
+        Some(Token::Id(id))
+    }).into()));
+
+    let mut spaces = regex::parse("[ ,]+").unwrap();
+    spaces.concat(Nfa::new_accepting(stringify!({
+        None
+    }).into()));
+
+    let mut nfa = keyword("select");
+    nfa.combine(keyword("from"));
+    nfa.combine(keyword("where"));
+    nfa.combine(id);
+    nfa.combine(spaces);
+
+    let dfa = Dfa::from_nfa(&nfa);
+
+    let outfile = Path::new(&env::var("OUT_DIR").unwrap())
+        .join("case-insensitive.rs");
+
+    let mut out = File::create(outfile).unwrap();
+
+    let mut gen = CodeGen::new();
+
+    gen.set_token_type("Token");
+
+    gen.generate(&dfa, &mut out).unwrap();
+}
+
+pub fn spec() {
+    Build::new("src/spec.parsl").run().unwrap();
+}
+
+pub fn modes() {
+    Build::new("src/modes.parsl").run().unwrap();
+
+    let mut build = Build::new("src/modes.parsl");
+
+    build.set_output("modes-table.rs");
+    build.codegen_mut().set_backend(Backend::Table);
+
+    build.run().unwrap();
+}
+
+pub fn positions() {
+    let mut word = regex::parse("\\S+").unwrap();
+    word.concat(Nfa::new_accepting(stringify!({
+        Some(_lexer_match.span())
+    }).into()));
+
+    // Match line breaks one character at a time to make sure "\r\n"
+    // is handled across matches
+    let mut space = regex::parse("\\s").unwrap();
+    space.concat(Nfa::new_accepting(stringify!({
+        None
+    }).into()));
+
+    let mut nfa = word;
+    nfa.combine(space);
+
+    let dfa = Dfa::from_nfa(&nfa);
+
+    let units = [
+        ("positions-bytes.rs", ColumnUnit::Bytes),
+        ("positions-chars.rs", ColumnUnit::Chars),
+        ("positions-utf16.rs", ColumnUnit::Utf16),
+    ];
+
+    for &(file, unit) in units.iter() {
+        let outfile = Path::new(&env::var("OUT_DIR").unwrap()).join(file);
+
+        let mut out = File::create(outfile).unwrap();
+
+        let mut gen = CodeGen::new();
+
+        gen.set_token_type("Span");
+        gen.set_column_unit(unit);
+
+        gen.generate(&dfa, &mut out).unwrap();
+    }
+}
+
+pub fn bytes() {
+    // PNG signature
+    let mut magic = regex::parse("\\x89PNG\r\n\\x1a\n").unwrap();
+    magic.concat(Nfa::new_accepting(stringify!({
+        Some(Token::Magic)
+    }).into()));
+
+    let mut chunk = regex::parse("[A-Za-z]{4}").unwrap();
+    chunk.concat(Nfa::new_accepting(stringify!({
+        Some(Token::Chunk(_lexer_match.as_bytes().into_owned()))
+    }).into()));
+
+    let mut high = regex::parse("[\\x80-\\xff]+").unwrap();
+    high.concat(Nfa::new_accepting(stringify!({
+        Some(Token::High(_lexer_match.as_bytes().len()))
+    }).into()));
+
+    let mut control = regex::parse("[\\x00-\\x1f]").unwrap();
+    control.concat(Nfa::new_accepting(stringify!({
+        Some(Token::Control(_lexer_match.as_bytes()[0]))
+    }).into()));
+
+    let mut nfa = magic;
+    nfa.combine(chunk);
+    nfa.combine(high);
+    nfa.combine(control);
+
+    let dfa = Dfa::from_nfa(&nfa);
+
+    let outfile = Path::new(&env::var("OUT_DIR").unwrap()).join("bytes.rs");
+
+    let mut out = File::create(outfile).unwrap();
+
+    let mut gen = CodeGen::new();
+
+    gen.set_token_type("Token");
+    gen.set_input_mode(InputMode::Bytes);
+
+    gen.generate(&dfa, &mut out).unwrap();
+}
+
+pub fn slice() {
+    let mut word = regex::parse("\\p{XID_Continue}+").unwrap();
+    word.concat(Nfa::new_accepting(stringify!({
+        Some(Token::Word(_lexer_match.as_str()))
+    }).into()));
+
+    let mut quoted = regex::parse("'[^']*'").unwrap();
+    quoted.concat(Nfa::new_accepting(stringify!({
+        Some(Token::Quoted(unquote(_lexer_match.as_str())))
+    }).into()));
+
+    let mut spaces = regex::parse("\\s+").unwrap();
+    spaces.concat(Nfa::new_accepting(stringify!({
+        None
+    }).into()));
+
+    let mut nfa = word;
+    nfa.combine(quoted);
+    nfa.combine(spaces);
+
+    let modes = [(INITIAL_MODE.to_owned(), nfa)];
+
+    // Also generate the lexer running on UTF-8 bytes, its error
+    // action can see invalid UTF-8
+    let variants = [
+        ("slice.rs", InputMode::Utf8, Dfa::from_modes(&modes)),
+        ("slice-bytes.rs", InputMode::Utf8Bytes, Dfa::from_modes_utf8(&modes)),
+    ];
+
+    for &(file, mode, ref dfa) in variants.iter() {
+        let outfile = Path::new(&env::var("OUT_DIR").unwrap()).join(file);
+
+        let mut out = File::create(outfile).unwrap();
+
+        let mut gen = CodeGen::new();
+
+        gen.set_token_type("Token<'a>");
+        gen.set_input_mode(mode);
+        gen.set_input_source(InputSource::Slice);
+        gen.set_error_action(stringify!({
+            Some(Token::Invalid(_lexer_match.as_str_lossy()))
+        }));
+
+        gen.generate(dfa, &mut out).unwrap();
+    }
+}
+
+pub fn limits() {
+    let mut word = regex::parse("[a-z]+").unwrap();
+    word.concat(Nfa::new_accepting(stringify!({
+        Some(Token::Word)
+    }).into()));
+
+    let mut string = regex::parse("\"[^\"]*\"").unwrap();
+    string.concat(Nfa::new_accepting(stringify!({
+        Some(Token::Str)
+    }).into()));
+
+    let mut minus = regex::parse("-").unwrap();
+    minus.concat(Nfa::new_accepting(stringify!({
+        Some(Token::Minus)
+    }).into()));
+
+    let mut arrow = regex::parse("-+>").unwrap();
+    arrow.concat(Nfa::new_accepting(stringify!({
+        Some(Token::Arrow)
+    }).into()));
+
+    let mut spaces = regex::parse("\\s+").unwrap();
+    spaces.concat(Nfa::new_accepting(stringify!({
+        None
+    }).into()));
+
+    let mut nfa = word;
+    nfa.combine(string);
+    nfa.combine(minus);
+    nfa.combine(arrow);
+    nfa.combine(spaces);
+
+    let dfa = Dfa::from_nfa(&nfa);
+
+    let outfile = Path::new(&env::var("OUT_DIR").unwrap()).join("limits.rs");
+
+    let mut out = File::create(outfile).unwrap();
+
+    let mut gen = CodeGen::new();
+
+    gen.set_token_type("Token");
+    gen.set_max_match_length(16);
+    gen.set_max_lookahead(4);
+
+    gen.generate(&dfa, &mut out).unwrap();
+}
@@ -1,3 +1,6 @@
+#[macro_use]
+extern crate pars_lexer_macros;
+
 mod simple {
     include!(concat!(env!("OUT_DIR"), "/simple.rs"));
 
@@ -45,9 +48,52 @@ mod intervals {
         }
 
         match lexer.next_token() {
-            Err(LexerError::NoMatch(32)) => (),
+            Err(LexerError::NoMatch(ref e)) => {
+                assert_eq!(e.span.start.offset, 32);
+                assert_eq!(e.span.end.offset, 33);
+                assert_eq!(e.found, Some('0'));
+                assert_eq!(e.expected,
+                           [(' ', ' '), ('A', 'Z'), ('_', '_'), ('a', 'z')]);
+            }
             e => panic!("Expected match error, got {:?}", e),
         }
+
+        // The invalid input has been skipped
+        assert_eq!(lexer.next_token().unwrap(), Some("invalid".into()));
+        assert!(lexer.next_token().unwrap().is_none());
+    }
+
+    #[test]
+    fn errors() {
+        let mut buf: &[u8] = b"ab 0 \xffcd e\xc3";
+
+        let mut lexer = Lexer::new(&mut buf);
+
+        assert_eq!(lexer.next_token().unwrap(), Some("ab".into()));
+
+        let e = lexer.next_token().unwrap_err();
+
+        assert_eq!(e.to_string(),
+                   "1:4: unexpected character '0', \
+                    expected ' ', 'A'-'Z', '_', 'a'-'z'");
+
+        let e = lexer.next_token().unwrap_err();
+
+        assert_eq!(e.to_string(), "1:6: invalid UTF-8 sequence \"\\xff\"");
+
+        assert_eq!(lexer.next_token().unwrap(), Some("cd".into()));
+        assert_eq!(lexer.next_token().unwrap(), Some("e".into()));
+
+        match lexer.next_token() {
+            Err(LexerError::InvalidUtf8 { position, ref bytes }) => {
+                assert_eq!((position.line, position.column), (1, 11));
+                assert_eq!(position.offset, 10);
+                assert_eq!(*bytes, [0xc3]);
+            }
+            e => panic!("Expected UTF-8 error, got {:?}", e),
+        }
+
+        assert!(lexer.next_token().unwrap().is_none());
     }
 }
 
@@ -170,3 +216,1005 @@ mod c_basic {
         assert!(lexer.next_token().unwrap().is_none());
     }
 }
+
+mod regex {
+    include!(concat!(env!("OUT_DIR"), "/regex.rs"));
+
+    #[derive(Debug, PartialEq, Eq, Clone)]
+    pub enum Token {
+        Number(String),
+        Id(String),
+        Op(String),
+        Str(String),
+    }
+
+    #[test]
+    fn lex() {
+        use self::Token::*;
+
+        let mut buf: &[u8] = b"x1 = 2 ** y_ - 3.25\n\t* \"a \\\"b\\\"\" * 4.";
+
+        let expected = [
+            Id("x1".into()),
+            Op("=".into()),
+            Number("2".into()),
+            Op("**".into()),
+            Id("y_".into()),
+            Op("-".into()),
+            Number("3.25".into()),
+            Op("*".into()),
+            Str("\"a \\\"b\\\"\"".into()),
+            Op("*".into()),
+            Number("4".into()),
+        ];
+
+        let mut lexer = Lexer::new(&mut buf);
+
+        for t in expected.iter() {
+            assert_eq!(lexer.next_token().unwrap(), Some(t.clone()));
+        }
+
+        match lexer.next_token() {
+            Err(LexerError::NoMatch(ref e)) => {
+                assert_eq!(e.span.start.offset, 36);
+                assert_eq!(e.found, Some('.'));
+            }
+            e => panic!("Expected match error, got {:?}", e),
+        }
+
+        assert!(lexer.next_token().unwrap().is_none());
+
+        // Unterminated string
+        let mut buf: &[u8] = b"\"abc";
+
+        let mut lexer = Lexer::new(&mut buf);
+
+        match lexer.next_token() {
+            Err(LexerError::NoMatch(ref e)) => {
+                assert_eq!(e.found, None);
+                assert_eq!(e.expected,
+                           [('\0', '\u{d7ff}'), ('\u{e000}', '\u{10ffff}')]);
+                assert_eq!(e.to_string(),
+                           "1:1: unexpected end of input, \
+                            expected '\\0'-'\\u{d7ff}', '\\u{e000}'-'\\u{10ffff}'");
+            }
+            e => panic!("Expected match error, got {:?}", e),
+        }
+
+        assert_eq!(lexer.next_token().unwrap(), Some(Id("abc".into())));
+    }
+}
+
+mod unicode {
+    include!(concat!(env!("OUT_DIR"), "/unicode.rs"));
+
+    #[derive(Debug, PartialEq, Eq, Clone)]
+    pub enum Token {
+        Greek(String),
+        Id(String),
+        Number(String),
+    }
+
+    #[test]
+    fn lex() {
+        use self::Token::*;
+
+        let mut buf: &[u8] = "hello привет λόγος ٣١ été_2 ωx".as_bytes();
+
+        let expected = [
+            Id("hello".into()),
+            Id("привет".into()),
+            Greek("λόγος".into()),
+            Number("٣١".into()),
+            Id("été_2".into()),
+            Id("ωx".into()),
+        ];
+
+        let mut lexer = Lexer::new(&mut buf);
+
+        for t in expected.iter() {
+            assert_eq!(lexer.next_token().unwrap(), Some(t.clone()));
+        }
+
+        assert!(lexer.next_token().unwrap().is_none());
+    }
+
+    #[test]
+    fn streaming() {
+        use self::Token::*;
+
+        let line = "hello привет λόγος ٣١\n";
+        let count = 100000;
+
+        let input = line.repeat(count);
+        let mut buf = input.as_bytes();
+
+        let mut lexer = Lexer::new(&mut buf);
+
+        let mut tokens = 0;
+
+        while lexer.next_token().unwrap().is_some() {
+            tokens += 1;
+
+            // The consumed input is discarded
+            assert!(lexer.buffer.len() <= 2 * 4096);
+        }
+
+        assert_eq!(tokens, 4 * count);
+        // Positions are still relative to the start of the input
+        assert_eq!(lexer.position().offset, input.len());
+        assert_eq!(lexer.position().line, count + 1);
+
+        // A match larger than the buffer
+        let long = "x".repeat(10000);
+        let input = format!("{} {} ab", long, long);
+        let mut buf = input.as_bytes();
+
+        let mut lexer = Lexer::new(&mut buf);
+
+        assert_eq!(lexer.next_token().unwrap(), Some(Id(long.clone())));
+        assert_eq!(lexer.next_token().unwrap(), Some(Id(long.clone())));
+        assert_eq!(lexer.next_token().unwrap(), Some(Id("ab".into())));
+        assert_eq!(lexer.position().offset, 20004);
+        assert!(lexer.next_token().unwrap().is_none());
+    }
+
+    #[test]
+    fn byte_reads() {
+        use self::Token::*;
+        use std::io::{self, Read};
+
+        // Every match crosses several refills
+        struct ByteReader<'a>(&'a [u8]);
+
+        impl<'a> Read for ByteReader<'a> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                (&mut self.0).take(1).read(buf)
+            }
+        }
+
+        let input = "hello привет λόγος ٣١ ".repeat(100);
+
+        let mut lexer = Lexer::new(ByteReader(input.as_bytes()));
+
+        for _ in 0..100 {
+            assert_eq!(lexer.next_token().unwrap(), Some(Id("hello".into())));
+            assert_eq!(lexer.next_token().unwrap(), Some(Id("привет".into())));
+            assert_eq!(lexer.next_token().unwrap(), Some(Greek("λόγος".into())));
+            assert_eq!(lexer.next_token().unwrap(), Some(Number("٣١".into())));
+
+            // The buffer is compacted between the refills
+            assert!(lexer.buffer.len() < 32);
+        }
+
+        assert!(lexer.next_token().unwrap().is_none());
+
+        // Decoded matches are copied out of the buffer
+        let utf16: Vec<u8> = input.encode_utf16()
+            .flat_map(|u| vec![u as u8, (u >> 8) as u8])
+            .collect();
+
+        let mut lexer = Lexer::with_encoding(ByteReader(&utf16),
+                                             Encoding::Utf16Le);
+
+        for _ in 0..100 {
+            assert_eq!(lexer.next_token().unwrap(), Some(Id("hello".into())));
+            assert_eq!(lexer.next_token().unwrap(), Some(Id("привет".into())));
+            assert_eq!(lexer.next_token().unwrap(), Some(Greek("λόγος".into())));
+            assert_eq!(lexer.next_token().unwrap(), Some(Number("٣١".into())));
+
+            assert!(lexer.buffer.len() < 64);
+        }
+
+        assert!(lexer.next_token().unwrap().is_none());
+    }
+
+    #[test]
+    fn readers() {
+        use std::io::{self, Cursor, Read};
+        use std::thread;
+
+        let input = "hello привет λόγος ٣١ été_2 ωx";
+
+        fn collect<R: ::std::io::Read>(mut lexer: Lexer<R>) -> Vec<Token> {
+            let mut tokens = Vec::new();
+
+            while let Some(t) = lexer.next_token().unwrap() {
+                tokens.push(t);
+            }
+
+            tokens
+        }
+
+        let mut buf = input.as_bytes();
+        let expected = collect(Lexer::new(&mut buf));
+
+        assert_eq!(expected.len(), 6);
+
+        // The lexer owns its reader and can be moved to another thread
+        let lexer = Lexer::new(Cursor::new(input.to_owned()));
+
+        let tokens = thread::spawn(move || collect(lexer)).join().unwrap();
+
+        assert_eq!(tokens, expected);
+
+        // Small reads split the characters and the matches
+        struct Chunks<'a>(&'a [u8], usize);
+
+        impl<'a> Read for Chunks<'a> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let len = self.1.min(buf.len());
+
+                (&mut self.0).take(len as u64).read(buf)
+            }
+        }
+
+        for &size in [1, 3, 7].iter() {
+            let reader = Chunks(input.as_bytes(), size);
+
+            assert_eq!(collect(Lexer::new(reader)), expected);
+        }
+    }
+
+    #[test]
+    fn encodings() {
+        use self::Token::*;
+
+        fn utf16(s: &str, big_endian: bool) -> Vec<u8> {
+            let mut bytes = Vec::new();
+
+            for u in s.encode_utf16() {
+                let (hi, lo) = ((u >> 8) as u8, u as u8);
+
+                if big_endian {
+                    bytes.push(hi);
+                    bytes.push(lo);
+                } else {
+                    bytes.push(lo);
+                    bytes.push(hi);
+                }
+            }
+
+            bytes
+        }
+
+        let input = "été λόγος\n😀x";
+
+        for &big_endian in [false, true].iter() {
+            let bytes = utf16(input, big_endian);
+
+            let mut buf = &bytes[..];
+
+            let encoding =
+                if big_endian { Encoding::Utf16Be } else { Encoding::Utf16Le };
+
+            let mut lexer = Lexer::with_encoding(&mut buf, encoding);
+
+            assert_eq!(lexer.next_token().unwrap(), Some(Id("été".into())));
+            // Offsets are in UTF-16 bytes
+            assert_eq!(lexer.position().offset, 6);
+            assert_eq!(lexer.position().column, 4);
+            assert_eq!(lexer.next_token().unwrap(), Some(Greek("λόγος".into())));
+
+            // The emoji isn't an identifier
+            match lexer.next_token() {
+                Err(LexerError::NoMatch(e)) => {
+                    assert_eq!((e.span.start.line, e.span.start.column), (2, 1));
+                    assert_eq!((e.span.start.offset, e.span.end.offset), (20, 24));
+                    assert_eq!(e.found, Some('😀'));
+                }
+                r => panic!("unexpected result {:?}", r),
+            }
+
+            assert_eq!(lexer.next_token().unwrap(), Some(Id("x".into())));
+            assert!(lexer.next_token().unwrap().is_none());
+        }
+
+        // Byte order marks
+        let mut bytes = b"\xfe\xff".to_vec();
+        bytes.extend(utf16("λ a", true));
+
+        let mut buf = &bytes[..];
+        let mut lexer = Lexer::with_bom_detection(&mut buf, Encoding::Latin1);
+
+        assert_eq!(lexer.next_token().unwrap(), Some(Greek("λ".into())));
+        assert_eq!(lexer.encoding(), Encoding::Utf16Be);
+        assert_eq!(lexer.next_token().unwrap(), Some(Id("a".into())));
+        assert_eq!(lexer.position().offset, 8);
+
+        let mut buf: &[u8] = b"\xef\xbb\xbf\xce\xbb";
+        let mut lexer = Lexer::with_bom_detection(&mut buf, Encoding::Latin1);
+
+        assert_eq!(lexer.next_token().unwrap(), Some(Greek("λ".into())));
+        assert_eq!(lexer.encoding(), Encoding::Utf8);
+
+        // No BOM, fall back on Latin-1
+        let mut buf: &[u8] = b"caf\xe9 \xb5";
+        let mut lexer = Lexer::with_bom_detection(&mut buf, Encoding::Latin1);
+
+        assert_eq!(lexer.next_token().unwrap(), Some(Id("café".into())));
+        assert_eq!(lexer.next_token().unwrap(), Some(Id("µ".into())));
+        assert_eq!(lexer.encoding(), Encoding::Latin1);
+        assert_eq!(lexer.position().offset, 6);
+
+        // Unpaired surrogate and odd trailing byte
+        let mut bytes = utf16("a ", false);
+        bytes.extend(b"\x00\xdcb\x00c");
+
+        let mut buf = &bytes[..];
+        let mut lexer = Lexer::with_encoding(&mut buf, Encoding::Utf16Le);
+
+        assert_eq!(lexer.next_token().unwrap(), Some(Id("a".into())));
+
+        match lexer.next_token() {
+            Err(e @ LexerError::InvalidUtf16 { .. }) =>
+                assert_eq!(e.to_string(), "1:3: invalid UTF-16 sequence \"\\x00\\xdc\""),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        assert_eq!(lexer.next_token().unwrap(), Some(Id("b".into())));
+
+        match lexer.next_token() {
+            Err(LexerError::InvalidUtf16 { position, bytes }) => {
+                assert_eq!(position.offset, 8);
+                assert_eq!(bytes, b"c");
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        assert!(lexer.next_token().unwrap().is_none());
+    }
+
+    mod bytes {
+        use super::Token;
+
+        include!(concat!(env!("OUT_DIR"), "/unicode-bytes.rs"));
+    }
+
+    mod table {
+        use super::Token;
+
+        include!(concat!(env!("OUT_DIR"), "/unicode-table.rs"));
+    }
+
+    #[test]
+    fn table() {
+        let input = "hello привет λόγος ٣١ été_2 ωx 😀 ٣x\u{e000}";
+
+        let mut buf = input.as_bytes();
+        let mut table_buf = input.as_bytes();
+
+        let mut lexer = Lexer::new(&mut buf);
+        let mut table_lexer = table::Lexer::new(&mut table_buf);
+
+        loop {
+            let token = lexer.next();
+
+            assert_eq!(format!("{:?}", table_lexer.next()),
+                       format!("{:?}", token));
+            assert_eq!(table_lexer.position().offset, lexer.position().offset);
+
+            if token.is_none() {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn utf8_bytes() {
+        use self::Token::*;
+
+        let input = "hello привет λόγος ٣١ été_2 ωx";
+
+        let mut buf = input.as_bytes();
+        let mut bytes_buf = input.as_bytes();
+
+        let mut lexer = Lexer::new(&mut buf);
+        let mut bytes_lexer = bytes::Lexer::new(&mut bytes_buf);
+
+        loop {
+            let token = lexer.next_token().unwrap();
+
+            assert_eq!(bytes_lexer.next_token().unwrap(), token);
+            assert_eq!(bytes_lexer.position().column,
+                       lexer.position().column);
+
+            if token.is_none() {
+                break;
+            }
+        }
+
+        // Invalid UTF-8 doesn't match any rule
+        let mut buf: &[u8] = b"ab\xce\xce\xbb \xff\xbb\xe2\x82 \xce\xbb";
+
+        let mut lexer = bytes::Lexer::new(&mut buf);
+
+        assert_eq!(lexer.next_token().unwrap(), Some(Id("ab".into())));
+
+        match lexer.next_token() {
+            Err(bytes::LexerError::NoMatch(e)) => {
+                assert_eq!((e.span.start.offset, e.span.end.offset), (2, 3));
+                assert_eq!(e.found, Some(0xce));
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        assert_eq!(lexer.next_token().unwrap(), Some(Greek("λ".into())));
+
+        match lexer.next_token() {
+            Err(bytes::LexerError::NoMatch(e)) => {
+                assert_eq!((e.span.start.offset, e.span.end.offset), (6, 8));
+                assert_eq!(e.found, Some(0xff));
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        // The truncated sequence fails on the following space
+        match lexer.next_token() {
+            Err(bytes::LexerError::NoMatch(e)) => {
+                assert_eq!((e.span.start.offset, e.span.end.offset), (8, 10));
+                assert_eq!(e.found, Some(b' '));
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        assert_eq!(lexer.next_token().unwrap(), Some(Greek("λ".into())));
+        assert!(lexer.next_token().unwrap().is_none());
+    }
+}
+
+mod case_insensitive {
+    include!(concat!(env!("OUT_DIR"), "/case-insensitive.rs"));
+
+    #[derive(Debug, PartialEq, Eq, Clone)]
+    pub enum Token {
+        Keyword(String),
+        Id(String),
+    }
+
+    #[test]
+    fn lex() {
+        use self::Token::*;
+
+        let mut buf: &[u8] = b"SeLeCt a, from_x FROM t wHeRe";
+
+        let expected = [
+            Keyword("select".into()),
+            Id("a".into()),
+            Id("from_x".into()),
+            Keyword("from".into()),
+            Id("t".into()),
+            Keyword("where".into()),
+        ];
+
+        let mut lexer = Lexer::new(&mut buf);
+
+        for t in expected.iter() {
+            assert_eq!(lexer.next_token().unwrap(), Some(t.clone()));
+        }
+
+        assert!(lexer.next_token().unwrap().is_none());
+    }
+}
+
+mod spec {
+    include!(concat!(env!("OUT_DIR"), "/spec.rs"));
+
+    #[derive(Debug, PartialEq, Clone)]
+    pub enum Token {
+        Number(f64),
+        Let,
+        Id(String),
+        Op(char),
+        /// Invalid input and its line
+        Error(String, usize),
+    }
+
+    #[test]
+    fn lex() {
+        use self::Token::*;
+
+        let mut buf: &[u8] = b"LET x2 = @\xc3\xa9 (y + 1.5) * 3\nlet letter = x2 #!";
+
+        let expected = [
+            Let,
+            Id("x2".into()),
+            Op('='),
+            Error("@\u{e9}".into(), 1),
+            Op('('),
+            Id("y".into()),
+            Op('+'),
+            Number(1.5),
+            Op(')'),
+            Op('*'),
+            Number(3.),
+            Let,
+            Id("letter".into()),
+            Op('='),
+            Id("x2".into()),
+            Error("#!".into(), 2),
+        ];
+
+        let mut lexer = Lexer::new(&mut buf);
+
+        for t in expected.iter() {
+            assert_eq!(lexer.next_token().unwrap(), Some(t.clone()));
+        }
+
+        assert!(lexer.next_token().unwrap().is_none());
+    }
+
+    #[test]
+    fn iterator() {
+        use self::Token::*;
+        use std::io::{self, Read};
+
+        let mut buf: &[u8] = b"let x = @2\n  y";
+
+        let mut lexer = Lexer::new(&mut buf);
+
+        let tokens: Result<Vec<Token>, LexerError> = lexer.by_ref().collect();
+
+        assert_eq!(tokens.unwrap(),
+                   [Let, Id("x".into()), Op('='), Error("@".into(), 1),
+                    Number(2.), Id("y".into())]);
+
+        // Fused after the end of the input
+        assert!(lexer.next().is_none());
+        assert!(lexer.next().is_none());
+
+        let mut buf: &[u8] = b"let x = @2\n  y";
+
+        let mut lexer = Lexer::new(&mut buf);
+
+        let spans: Vec<_> = lexer.tokens()
+            .map(|r| {
+                let (span, _) = r.unwrap();
+
+                (span.start.offset, span.end.offset, span.start.line)
+            })
+            .collect();
+
+        assert_eq!(spans, [(0, 3, 1), (4, 5, 1), (6, 7, 1), (8, 9, 1),
+                           (9, 10, 1), (13, 14, 2)]);
+
+        // I/O errors are fatal
+        struct Failing;
+
+        impl Read for Failing {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::Other, "read failed"))
+            }
+        }
+
+        let mut lexer = Lexer::new(Failing);
+
+        match lexer.next() {
+            Some(Err(LexerError::IoError(_))) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        assert!(lexer.next().is_none());
+    }
+}
+
+mod modes {
+    include!(concat!(env!("OUT_DIR"), "/modes.rs"));
+
+    #[derive(Debug, PartialEq, Clone)]
+    pub enum Token {
+        Id(String),
+        StrStart,
+        StrEnd,
+        Text(String),
+        InterpStart,
+        InterpEnd,
+    }
+
+    #[test]
+    fn lex() {
+        use self::Token::*;
+
+        let mut buf: &[u8] =
+            b"a \"x /* $ ${b \"${c}$\"} z\" /* c /* d */ \" */ e";
+
+        let expected = [
+            Id("a".into()),
+            StrStart,
+            Text("x /* ".into()),
+            Text("$".into()),
+            Text(" ".into()),
+            InterpStart,
+            Id("b".into()),
+            StrStart,
+            InterpStart,
+            Id("c".into()),
+            InterpEnd,
+            Text("$".into()),
+            StrEnd,
+            InterpEnd,
+            Text(" z".into()),
+            StrEnd,
+            Id("e".into()),
+        ];
+
+        let mut lexer = Lexer::new(&mut buf);
+
+        assert_eq!(lexer.mode(), Mode::Initial);
+
+        for t in expected.iter() {
+            assert_eq!(lexer.next_token().unwrap(), Some(t.clone()));
+        }
+
+        assert!(lexer.next_token().unwrap().is_none());
+
+        assert_eq!(lexer.mode(), Mode::Initial);
+        assert_eq!(lexer.pop_mode(), None);
+    }
+
+    mod table {
+        use super::Token;
+
+        include!(concat!(env!("OUT_DIR"), "/modes-table.rs"));
+    }
+
+    #[test]
+    fn table() {
+        let input = "a \"x /* $ ${b \"${c}$\"} z\" /* c /* d */ \" */ e \"${";
+
+        let mut buf = input.as_bytes();
+        let mut table_buf = input.as_bytes();
+
+        let tokens: Vec<_> = Lexer::new(&mut buf).collect();
+        let table_tokens: Vec<_> = table::Lexer::new(&mut table_buf).collect();
+
+        assert_eq!(format!("{:?}", table_tokens), format!("{:?}", tokens));
+    }
+}
+
+mod positions {
+    const INPUT: &'static str = "ab \u{e9}\u{1d11e}x\r\nz\rw\n\ny";
+
+    /// Collect the (line, column) of the start and end of each token
+    #[cfg(test)]
+    macro_rules! spans {
+        ($lexer:ident) => {{
+            let mut buf = INPUT.as_bytes();
+            let mut lexer = $lexer::Lexer::new(&mut buf);
+            let mut spans = Vec::new();
+
+            while let Some(s) = lexer.next_token().unwrap() {
+                spans.push(((s.start.line, s.start.column),
+                            (s.end.line, s.end.column)));
+            }
+
+            assert_eq!(lexer.position().line, 5);
+            assert_eq!(lexer.position().offset, INPUT.len());
+
+            spans
+        }}
+    }
+
+    mod bytes {
+        include!(concat!(env!("OUT_DIR"), "/positions-bytes.rs"));
+    }
+
+    mod chars {
+        include!(concat!(env!("OUT_DIR"), "/positions-chars.rs"));
+    }
+
+    mod utf16 {
+        include!(concat!(env!("OUT_DIR"), "/positions-utf16.rs"));
+    }
+
+    #[test]
+    fn columns() {
+        assert_eq!(spans!(bytes), [((1, 1), (1, 3)),
+                                   ((1, 4), (1, 11)),
+                                   ((2, 1), (2, 2)),
+                                   ((3, 1), (3, 2)),
+                                   ((5, 1), (5, 2))]);
+
+        assert_eq!(spans!(chars), [((1, 1), (1, 3)),
+                                   ((1, 4), (1, 7)),
+                                   ((2, 1), (2, 2)),
+                                   ((3, 1), (3, 2)),
+                                   ((5, 1), (5, 2))]);
+
+        assert_eq!(spans!(utf16), [((1, 1), (1, 3)),
+                                   ((1, 4), (1, 8)),
+                                   ((2, 1), (2, 2)),
+                                   ((3, 1), (3, 2)),
+                                   ((5, 1), (5, 2))]);
+    }
+
+    #[test]
+    fn offsets() {
+        let mut buf = INPUT.as_bytes();
+        let mut lexer = chars::Lexer::new(&mut buf);
+
+        let ab = lexer.next_token().unwrap().unwrap();
+        let word = lexer.next_token().unwrap().unwrap();
+
+        assert_eq!((ab.start.offset, ab.end.offset), (0, 2));
+        assert_eq!((word.start.offset, word.end.offset), (3, 10));
+        assert_eq!(word.start.to_string(), "1:4");
+    }
+}
+
+mod bytes {
+    include!(concat!(env!("OUT_DIR"), "/bytes.rs"));
+
+    #[derive(Debug, PartialEq, Eq, Clone)]
+    pub enum Token {
+        Magic,
+        Chunk(Vec<u8>),
+        High(usize),
+        Control(u8),
+    }
+
+    #[test]
+    fn lex() {
+        use self::Token::*;
+
+        let mut buf: &[u8] = b"\x89PNG\r\n\x1a\nIHDR\xff\xfe\xc3\x00IEND\x89PN!\x01";
+
+        let expected = [
+            Magic,
+            Chunk(b"IHDR".to_vec()),
+            High(3),
+            Control(0),
+            Chunk(b"IEND".to_vec()),
+            High(1),
+        ];
+
+        let mut lexer = Lexer::new(&mut buf);
+
+        for t in expected.iter() {
+            assert_eq!(lexer.next_token().unwrap(), Some(t.clone()));
+        }
+
+        // "PN!" isn't a valid chunk name. The lexer resynchronizes on
+        // the 'N' which can start a chunk name and fails again.
+        match lexer.next_token() {
+            Err(LexerError::NoMatch(ref e)) => {
+                assert_eq!(e.span.start.offset, 21);
+                assert_eq!(e.span.end.offset, 22);
+                assert_eq!(e.found, Some(b'!'));
+                assert_eq!(e.expected, [(b'A', b'Z'), (b'a', b'z')]);
+                assert_eq!(e.to_string(),
+                           "3:14: unexpected byte b'!', expected b'A'-b'Z', b'a'-b'z'");
+            }
+            e => panic!("Expected match error, got {:?}", e),
+        }
+
+        match lexer.next_token() {
+            Err(LexerError::NoMatch(ref e)) => {
+                assert_eq!(e.span.start.offset, 22);
+                assert_eq!(e.span.end.offset, 24);
+            }
+            e => panic!("Expected match error, got {:?}", e),
+        }
+
+        assert_eq!(lexer.next_token().unwrap(), Some(Control(1)));
+        assert!(lexer.next_token().unwrap().is_none());
+    }
+}
+
+mod slice {
+    include!(concat!(env!("OUT_DIR"), "/slice.rs"));
+
+    use std::borrow::Cow;
+
+    #[derive(Debug, PartialEq, Eq, Clone)]
+    pub enum Token<'a> {
+        Word(&'a str),
+        Quoted(&'a str),
+        /// Skipped input, only copied if it's not valid UTF-8
+        Invalid(Cow<'a, str>),
+    }
+
+    /// Strip the quotes around `s`
+    fn unquote(s: &str) -> &str {
+        &s[1..s.len() - 1]
+    }
+
+    /// Lex `input`, the tokens borrow from it and outlive the lexer
+    #[cfg(test)]
+    fn tokens<'a>(input: &'a str) -> Vec<Token<'a>> {
+        let mut lexer = Lexer::new(input);
+        let mut tokens = Vec::new();
+
+        while let Some(t) = lexer.next_token().unwrap() {
+            tokens.push(t);
+        }
+
+        assert_eq!(lexer.position().offset, input.len());
+
+        tokens
+    }
+
+    #[test]
+    fn lex() {
+        use self::Token::*;
+
+        let input = String::from("été 'a b' -- x2 'ω");
+
+        assert_eq!(tokens(&input),
+                   [Word("été"),
+                    Quoted("a b"),
+                    Invalid("--".into()),
+                    Word("x2"),
+                    Invalid("'".into()),
+                    Word("ω")]);
+
+        // Tokens point into the input
+        match tokens(&input)[0] {
+            Word(w) => assert_eq!(w.as_ptr(), input.as_ptr()),
+            ref t => panic!("unexpected token {:?}", t),
+        }
+
+        // Byte slices work too
+        let mut lexer = Lexer::new(b"ab\xff cd");
+
+        assert_eq!(lexer.next_token().unwrap(), Some(Word("ab")));
+
+        match lexer.next_token() {
+            Err(LexerError::InvalidUtf8 { position, .. }) =>
+                assert_eq!(position.offset, 2),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        assert_eq!(lexer.next_token().unwrap(), Some(Word("cd")));
+        assert!(lexer.next_token().unwrap().is_none());
+    }
+
+    mod bytes {
+        use super::{Token, unquote};
+
+        include!(concat!(env!("OUT_DIR"), "/slice-bytes.rs"));
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        use self::Token::*;
+
+        let mut lexer = bytes::Lexer::new(b"ab \xff! '\xc3\xa9' e");
+
+        // Only the invalid bytes are replaced
+        assert_eq!(lexer.next_token().unwrap(), Some(Word("ab")));
+        assert_eq!(lexer.next_token().unwrap(),
+                   Some(Invalid("\u{fffd}!".into())));
+        assert_eq!(lexer.next_token().unwrap(), Some(Quoted("é")));
+        assert_eq!(lexer.next_token().unwrap(), Some(Word("e")));
+        assert!(lexer.next_token().unwrap().is_none());
+    }
+}
+
+mod limits {
+    include!(concat!(env!("OUT_DIR"), "/limits.rs"));
+
+    #[derive(Debug, PartialEq, Eq, Clone)]
+    pub enum Token {
+        Word,
+        Str,
+        Minus,
+        Arrow,
+    }
+
+    #[test]
+    fn lex() {
+        use self::Token::*;
+
+        // Matches up to the limits are fine
+        let mut buf: &[u8] = b"abcdefghijklmnop \"abcdefghijklmn\" ---> - ----> x";
+
+        let mut lexer = Lexer::new(&mut buf);
+
+        for t in [Word, Str, Arrow, Minus, Arrow, Word].iter() {
+            assert_eq!(lexer.next_token().unwrap(), Some(t.clone()));
+        }
+
+        assert!(lexer.next_token().unwrap().is_none());
+
+        let mut buf: &[u8] = b"ab abcdefghijklmnopq \"abcdefghijklmnopqrstuvwxyz --------- x";
+
+        let mut lexer = Lexer::new(&mut buf);
+
+        assert_eq!(lexer.next_token().unwrap(), Some(Word));
+
+        match lexer.next_token() {
+            Err(LexerError::LimitExceeded { position, limit }) => {
+                assert_eq!(position.offset, 3);
+                assert_eq!(limit, Limit::MatchLength(16));
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        // The input read so far is skipped
+        assert_eq!(lexer.position().offset, 20);
+
+        match lexer.next_token() {
+            Err(e @ LexerError::LimitExceeded { .. }) =>
+                assert_eq!(e.to_string(), "1:22: match longer than 16 bytes"),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        assert_eq!(lexer.next_token().unwrap(), Some(Word));
+
+        // The '-' matches but the arrow never comes
+        match lexer.next_token() {
+            Err(LexerError::LimitExceeded { position, limit }) => {
+                assert_eq!(position.offset, 49);
+                assert_eq!(limit, Limit::Lookahead(4));
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        assert_eq!(lexer.position().offset, 55);
+        assert_eq!(lexer.next_token().unwrap(), Some(Minus));
+        assert_eq!(lexer.next_token().unwrap(), Some(Minus));
+        assert_eq!(lexer.next_token().unwrap(), Some(Minus));
+        assert_eq!(lexer.next_token().unwrap(), Some(Word));
+        assert!(lexer.next_token().unwrap().is_none());
+    }
+}
+
+mod macro_lexer {
+    lexer! {
+        token Token;
+        mode Comment;
+
+        error => { Some(Token::Invalid(_lexer_match.as_str().into_owned())) }
+
+        <Initial, Comment> r"/\*" => { self.push_mode(Mode::Comment); None }
+        <Comment> r"\*/" => { self.pop_mode(); None }
+        <Comment> r"[^*/]+|[*/]" => { None }
+
+        r"[0-9]+" => {
+            Some(Token::Number(_lexer_match.as_str().parse().unwrap()))
+        }
+        r"(?i)if" => { Some(Token::If) }
+        r"\p{XID_Start}\p{XID_Continue}*" => {
+            Some(Token::Id(_lexer_match.as_str().into()))
+        }
+        "[+*/-]" => { Some(Token::Op(_lexer_match.as_str().chars().next().unwrap())) },
+        r"\s+" => { None },
+    }
+
+    #[derive(Debug, PartialEq, Clone)]
+    pub enum Token {
+        Number(u32),
+        If,
+        Id(String),
+        Op(char),
+        Invalid(String),
+    }
+
+    #[test]
+    fn lex() {
+        use self::Token::*;
+
+        let mut buf: &[u8] = "IF x + 42 /* IF /* y */ */ * éte - iffy §§".as_bytes();
+
+        let expected = [
+            If,
+            Id("x".into()),
+            Op('+'),
+            Number(42),
+            Op('*'),
+            Id("éte".into()),
+            Op('-'),
+            Id("iffy".into()),
+            Invalid("§§".into()),
+        ];
+
+        let mut lexer = Lexer::new(&mut buf);
+
+        for t in expected.iter() {
+            assert_eq!(lexer.next_token().unwrap(), Some(t.clone()));
+        }
+
+        assert!(lexer.next_token().unwrap().is_none());
+    }
+}